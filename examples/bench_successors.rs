@@ -0,0 +1,164 @@
+// 遷移先の生成のベンチマーク。以前の再帰的な実装 (プロセスごとに1段再帰し、
+// 遷移ごとに `acc.clone()` と `insert(0, ..)` をする) と現在の実装を比べる。
+//
+//     cargo run --release --example bench_successors [プロセス数]
+use ddsv::data::{collect_trans_into, concurrent_composition, Network, Process, Trans};
+use ddsv::error::Error;
+use std::collections::{HashSet, VecDeque};
+use std::env;
+use std::time::{Duration, Instant};
+
+type State = (u32, Vec<String>);
+
+fn always_true(_r: &u32) -> bool {
+    true
+}
+fn return_copied(r: &u32) -> u32 {
+    *r
+}
+fn increment(r: &u32) -> u32 {
+    (r + 1) % 4
+}
+fn reset(_r: &u32) -> u32 {
+    0
+}
+
+// 場所を3つ持つプロセスを `n` 個並べる。状態数は 3^n * 4 程度
+fn processes(n: usize) -> Vec<Process<u32>> {
+    (0..n)
+        .map(|i| {
+            let l = (0..3).map(|j| format!("P{}_{}", i, j)).collect::<Vec<_>>();
+            Process::new(vec![
+                (
+                    &l[0],
+                    vec![
+                        Trans::new("read", &l[1], always_true, return_copied),
+                        Trans::new("reset", &l[2], always_true, reset),
+                    ],
+                ),
+                (
+                    &l[1],
+                    vec![Trans::new("inc", &l[2], always_true, increment)],
+                ),
+                (
+                    &l[2],
+                    vec![Trans::new("back", &l[0], always_true, return_copied)],
+                ),
+            ])
+        })
+        .collect()
+}
+
+fn legacy_calc_transitions(
+    acc: Vec<(String, State)>,
+    r: &u32,
+    rs: &[String],
+    ls: &[String],
+    transitions: &[Trans<u32>],
+) -> Vec<(String, State)> {
+    transitions.iter().fold(acc, |acc_, trans| {
+        if trans.guard.holds(r, || unreachable!()) {
+            let mut v1 = ls.to_vec();
+            v1.insert(0, trans.location.clone());
+            let mut locations = rs.to_vec();
+            locations.reverse();
+            locations.append(&mut v1);
+            let r_ = trans.action.apply(r, || unreachable!()).remove(0).unwrap();
+            let mut acc__ = acc_.clone();
+            acc__.insert(0, (trans.label.clone(), (r_, locations)));
+            acc__
+        } else {
+            acc_
+        }
+    })
+}
+
+fn legacy_collect_trans(
+    acc: Vec<(String, State)>,
+    r: &u32,
+    rs: &[String],
+    ls: &[String],
+    ps: &[Process<u32>],
+) -> Vec<(String, State)> {
+    match (ls, ps) {
+        ([], []) => acc,
+        (l, p) => {
+            let (location, ls_2) = l.split_first().unwrap();
+            let (process, ps_2) = p.split_first().unwrap();
+            let transitions = &process.0.iter().find(|(l, _)| l == location).unwrap().1;
+            let acc = legacy_calc_transitions(acc, r, rs, ls_2, transitions);
+            let mut rs_2 = vec![location.clone()];
+            rs_2.extend(rs.to_vec());
+            legacy_collect_trans(acc, r, rs_2.as_slice(), ls_2, ps_2)
+        }
+    }
+}
+
+// 以前の実装で状態空間を幅優先で探索し、状態数を返す
+fn legacy_bfs(ps: &[Process<u32>]) -> usize {
+    let s0 = (0, ps.iter().map(|p| p.0[0].0.clone()).collect::<Vec<_>>());
+    let mut visited = HashSet::new();
+    visited.insert(s0.clone());
+    let mut que = VecDeque::new();
+    que.push_back(s0);
+    while let Some((r, locs)) = que.pop_front() {
+        for (_, target) in legacy_collect_trans(vec![], &r, &[], &locs, ps) {
+            if visited.insert(target.clone()) {
+                que.push_back(target);
+            }
+        }
+    }
+    visited.len()
+}
+
+fn time<F: FnMut() -> usize>(mut f: F) -> (usize, Duration) {
+    let start = Instant::now();
+    let n = f();
+    (n, start.elapsed())
+}
+
+fn main() -> Result<(), Error> {
+    let n = env::args().nth(1).and_then(|s| s.parse().ok()).unwrap_or(8);
+    let ps = processes(n);
+
+    let (legacy_states, legacy) = time(|| legacy_bfs(&ps));
+    let (states, current) = time(|| concurrent_composition(&0, &ps).unwrap().states.len());
+    assert_eq!(legacy_states, states);
+    println!("{} processes, {} states", n, states);
+    println!(
+        "composition  legacy: {:>10.3?}  current: {:>10.3?}  speedup: {:.1}x",
+        legacy,
+        current,
+        legacy.as_secs_f64() / current.as_secs_f64()
+    );
+
+    // 到達したすべての状態について、遷移先の生成だけを比べる
+    let lts = concurrent_composition(&0, &ps)?;
+    let net = Network::new(&ps)?;
+    let packed = lts.states.iter().collect::<Vec<_>>();
+    let unpacked = packed.iter().map(|s| lts.unpack(s)).collect::<Vec<_>>();
+    let (legacy_succ, legacy) = time(|| {
+        unpacked
+            .iter()
+            .map(|(r, locs)| legacy_collect_trans(vec![], r, &[], locs, &ps).len())
+            .sum()
+    });
+    let mut buf = vec![];
+    let (succ, current) = time(|| {
+        packed
+            .iter()
+            .map(|s| {
+                collect_trans_into(&mut buf, &net, s);
+                buf.len()
+            })
+            .sum()
+    });
+    assert_eq!(legacy_succ, succ);
+    println!(
+        "successors   legacy: {:>10.3?}  current: {:>10.3?}  speedup: {:.1}x",
+        legacy,
+        current,
+        legacy.as_secs_f64() / current.as_secs_f64()
+    );
+    Ok(())
+}
@@ -1,6 +1,8 @@
 use ddsv::data;
-use ddsv::data::{Process, Trans};
-use env_logger;
+use ddsv::data::{DotFormat, DotOptions, Process, Termination, Trans};
+use ddsv::error::Error;
+use ddsv::race;
+use ddsv::race::{RaceCheck, Var};
 use std::env;
 use std::fmt;
 use std::io::Write;
@@ -24,7 +26,7 @@ impl fmt::Debug for SharedVars {
     }
 }
 
-fn main() {
+fn main() -> Result<(), Error> {
     env::set_var("RUST_LOG", "info");
     env_logger::builder()
         .format(|buf, record| {
@@ -38,46 +40,61 @@ fn main() {
         })
         .init();
     let r0 = SharedVars::new();
-    let process_p = Process {
-        0: vec![
-            (
-                String::from("P0"),
-                vec![Trans::new("read", "P1", always_true, move_x_to_t1)],
-            ),
-            (
-                String::from("P1"),
-                vec![Trans::new("inc", "P2", always_true, increment_t1)],
-            ),
-            (
-                String::from("P2"),
-                vec![Trans::new("write", "P3", always_true, move_t1_to_x)],
-            ),
-            (String::from("P3"), vec![]),
-        ],
-    };
+    let process_p = Process(vec![
+        (
+            String::from("P0"),
+            vec![Trans::new("read", "P1", always_true, move_x_to_t1)],
+        ),
+        (
+            String::from("P1"),
+            vec![Trans::new("inc", "P2", always_true, increment_t1)],
+        ),
+        (
+            String::from("P2"),
+            vec![Trans::new("write", "P3", always_true, move_t1_to_x)],
+        ),
+        (String::from("P3"), vec![]),
+    ]);
 
-    let process_q = Process {
-        0: vec![
-            (
-                String::from("Q0"),
-                vec![Trans::new("read", "Q1", always_true, move_x_to_t2)],
-            ),
-            (
-                String::from("Q1"),
-                vec![Trans::new("inc", "Q2", always_true, increment_t2)],
-            ),
-            (
-                String::from("Q2"),
-                vec![Trans::new("write", "Q3", always_true, move_t2_to_x)],
-            ),
-            (String::from("Q3"), vec![]),
-        ],
+    let process_q = Process(vec![
+        (
+            String::from("Q0"),
+            vec![Trans::new("read", "Q1", always_true, move_x_to_t2)],
+        ),
+        (
+            String::from("Q1"),
+            vec![Trans::new("inc", "Q2", always_true, increment_t2)],
+        ),
+        (
+            String::from("Q2"),
+            vec![Trans::new("write", "Q3", always_true, move_t2_to_x)],
+        ),
+        (String::from("Q3"), vec![]),
+    ]);
+    process_p.viz_process("m_inc2_P", DotFormat::from_env())?;
+    process_q.viz_process("m_inc2_Q", DotFormat::from_env())?;
+    let ps = [process_p, process_q];
+    let lts = data::concurrent_composition(&r0, &ps)?;
+    data::lts_print_deadlock_with(&lts, &Termination::end_locations(&[&["P3"], &["Q3"]]))?;
+    // 更新が失われるのは x のデータ競合による
+    let races = RaceCheck::new(vec![Var::new("x", get_x, set_x)]).check(&lts, &ps)?;
+    race::lts_print_races(&lts, &races)?;
+    let opts = DotOptions {
+        termination: Some(Termination::end_locations(&[&["P3"], &["Q3"]])),
+        ..DotOptions::default()
     };
-    process_p.viz_process("m_inc2_P");
-    process_q.viz_process("m_inc2_Q");
-    let lts = data::concurrent_composition(&r0, &vec![process_p, process_q]);
-    data::lts_print_deadlock(&lts);
-    data::viz_lts("m_inc2", &lts);
+    data::viz_lts_with("m_inc2", &lts, &opts, DotFormat::from_env())
+}
+
+// 競合を検査する共有変数
+fn get_x(r: &SharedVars) -> i64 {
+    r.x as i64
+}
+
+fn set_x(r: &SharedVars, x: i64) -> SharedVars {
+    let mut s = r.clone();
+    s.x = x as i32;
+    s
 }
 
 // guard
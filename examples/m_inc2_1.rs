@@ -1,6 +1,6 @@
 use ddsv::data;
-use ddsv::data::{Process, Trans};
-use env_logger;
+use ddsv::data::{DotFormat, DotOptions, Process, Termination, Trans};
+use ddsv::error::Error;
 use std::env;
 use std::fmt;
 use std::io::Write;
@@ -33,7 +33,7 @@ impl fmt::Debug for SharedVars {
     }
 }
 
-fn main() {
+fn main() -> Result<(), Error> {
     env::set_var("RUST_LOG", "info");
     env_logger::builder()
         .format(|buf, record| {
@@ -47,63 +47,63 @@ fn main() {
         })
         .init();
     let r0 = SharedVars::new();
-    let process_p = Process {
-        0: vec![
-            (
-                String::from("P0"),
-                vec![Trans::new("lock", "P1", is_locked, lock)],
-            ),
-            (
-                String::from("P1"),
-                vec![Trans::new("read", "P2", always_true, move_x_to_t1)],
-            ),
-            (
-                String::from("P2"),
-                vec![Trans::new("inc", "P3", always_true, increment_t1)],
-            ),
-            (
-                String::from("P3"),
-                vec![Trans::new("write", "P4", always_true, move_t1_to_x)],
-            ),
-            (
-                String::from("P4"),
-                vec![Trans::new("unlock", "P5", always_true, unlock)],
-            ),
-            (String::from("P5"), vec![]),
-        ],
+    let process_p = Process(vec![
+        (
+            String::from("P0"),
+            vec![Trans::new("lock", "P1", is_locked, lock)],
+        ),
+        (
+            String::from("P1"),
+            vec![Trans::new("read", "P2", always_true, move_x_to_t1)],
+        ),
+        (
+            String::from("P2"),
+            vec![Trans::new("inc", "P3", always_true, increment_t1)],
+        ),
+        (
+            String::from("P3"),
+            vec![Trans::new("write", "P4", always_true, move_t1_to_x)],
+        ),
+        (
+            String::from("P4"),
+            vec![Trans::new("unlock", "P5", always_true, unlock)],
+        ),
+        (String::from("P5"), vec![]),
+    ]);
+
+    let process_q = Process(vec![
+        (
+            String::from("Q0"),
+            vec![Trans::new("lock", "Q1", is_locked, lock)],
+        ),
+        (
+            String::from("Q1"),
+            vec![Trans::new("read", "Q2", always_true, move_x_to_t2)],
+        ),
+        (
+            String::from("Q2"),
+            vec![Trans::new("inc", "Q3", always_true, increment_t2)],
+        ),
+        (
+            String::from("Q3"),
+            vec![Trans::new("write", "Q4", always_true, move_t2_to_x)],
+        ),
+        (
+            String::from("Q4"),
+            vec![Trans::new("unlock", "Q5", always_true, unlock)],
+        ),
+        (String::from("Q5"), vec![]),
+    ]);
+
+    process_p.viz_process("m_inc2_1_P", DotFormat::from_env())?;
+    process_q.viz_process("m_inc2_1_Q", DotFormat::from_env())?;
+    let lts = data::concurrent_composition(&r0, &[process_p, process_q])?;
+    data::lts_print_deadlock_with(&lts, &Termination::end_locations(&[&["P5"], &["Q5"]]))?;
+    let opts = DotOptions {
+        termination: Some(Termination::end_locations(&[&["P5"], &["Q5"]])),
+        ..DotOptions::default()
     };
-
-    let process_q = Process {
-        0: vec![
-            (
-                String::from("Q0"),
-                vec![Trans::new("lock", "Q1", is_locked, lock)],
-            ),
-            (
-                String::from("Q1"),
-                vec![Trans::new("read", "Q2", always_true, move_x_to_t2)],
-            ),
-            (
-                String::from("Q2"),
-                vec![Trans::new("inc", "Q3", always_true, increment_t2)],
-            ),
-            (
-                String::from("Q3"),
-                vec![Trans::new("write", "Q4", always_true, move_t2_to_x)],
-            ),
-            (
-                String::from("Q4"),
-                vec![Trans::new("unlock", "Q5", always_true, unlock)],
-            ),
-            (String::from("Q5"), vec![]),
-        ],
-    };
-
-    process_p.viz_process("m_inc2_1_P");
-    process_q.viz_process("m_inc2_1_Q");
-    let lts = data::concurrent_composition(&r0, &vec![process_p, process_q]);
-    data::lts_print_deadlock(&lts);
-    data::viz_lts("m_inc2_1", &lts);
+    data::viz_lts_with("m_inc2_1", &lts, &opts, DotFormat::from_env())
 }
 
 // guard
@@ -0,0 +1,102 @@
+// m_inc2 の2つのプロセスを `Program` で書き、読み書きをミューテックスで囲む
+use ddsv::data;
+use ddsv::data::{DotFormat, Termination};
+use ddsv::error::Error;
+use ddsv::program::Program;
+use ddsv::sync::{Has, Mutex};
+use std::env;
+use std::fmt;
+use std::io::Write;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+struct SharedVars {
+    x: i32,
+    t: [i32; 2],
+    m: Mutex,
+}
+
+impl SharedVars {
+    fn new() -> SharedVars {
+        SharedVars {
+            x: 0,
+            t: [0, 0],
+            m: Mutex::default(),
+        }
+    }
+}
+
+impl Has<Mutex, 0> for SharedVars {
+    fn get(&self) -> &Mutex {
+        &self.m
+    }
+    fn get_mut(&mut self) -> &mut Mutex {
+        &mut self.m
+    }
+}
+
+impl fmt::Debug for SharedVars {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let owner = self.m.owner.map_or(String::from("-"), |p| p.to_string());
+        f.write_fmt(format_args!(
+            "x={} t1={} t2={} m={}",
+            self.x, self.t[0], self.t[1], owner
+        ))
+    }
+}
+
+// `P` 番目のプロセスのプログラム
+fn program<const P: usize>() -> Program<SharedVars> {
+    let mut p = Program::new();
+    p.lock::<0, P>("m")
+        .assign("t = x", move_x_to_t::<P>)
+        .assign("t = t + 1", increment_t::<P>)
+        .assign("x = t", move_t_to_x::<P>)
+        .unlock::<0, P>("m");
+    p
+}
+
+fn main() -> Result<(), Error> {
+    env::set_var("RUST_LOG", "info");
+    env_logger::builder()
+        .format(|buf, record| {
+            writeln!(
+                buf,
+                "{}: L{} {}",
+                record.level(),
+                record.line().unwrap_or(0),
+                record.args()
+            )
+        })
+        .init();
+    let r0 = SharedVars::new();
+    let (program_p, program_q) = (program::<0>(), program::<1>());
+    print!("{}", program_p.listing());
+    for (location, line) in program_p.source_map("P") {
+        println!("{}\t{}", location, line);
+    }
+    let process_p = program_p.build("P")?;
+    let process_q = program_q.build("Q")?;
+    let lts = data::concurrent_composition(&r0, &[process_p, process_q])?;
+    // 5行のプログラムの終わりの場所は6番目
+    data::lts_print_deadlock_with(&lts, &Termination::end_locations(&[&["P6"], &["Q6"]]))?;
+    data::viz_lts("m_inc2_program", &lts, DotFormat::from_env())
+}
+
+// action
+fn increment_t<const P: usize>(r: &SharedVars) -> SharedVars {
+    let mut s = r.clone();
+    s.t[P] = r.t[P] + 1;
+    s
+}
+
+fn move_t_to_x<const P: usize>(r: &SharedVars) -> SharedVars {
+    let mut s = r.clone();
+    s.x = r.t[P];
+    s
+}
+
+fn move_x_to_t<const P: usize>(r: &SharedVars) -> SharedVars {
+    let mut s = r.clone();
+    s.t[P] = r.x;
+    s
+}
@@ -1,27 +1,30 @@
 use ddsv::data;
-use ddsv::data::{Process, Trans};
-use env_logger;
+use ddsv::data::{Composition, DotFormat, Process, Trans};
+use ddsv::error::Error;
+use ddsv::sweep;
 use std::env;
 use std::fmt;
-use std::io::Write;
+use std::io::{self, Write};
 
 #[derive(Clone, PartialEq, Eq, Hash)]
 struct SharedVars {
     mutex: i32,
     cond: i32,
     count: i32,
+    // バッファの大きさ。ガードから読めるように共有変数に入れておく
+    max_count: i32,
 }
 
-static MAX_COUNT: i32 = 3;
 static P_INDEX: i32 = 1;
 static Q_INDEX: i32 = 2;
 
 impl SharedVars {
-    fn new() -> SharedVars {
+    fn new(max_count: i32) -> SharedVars {
         SharedVars {
             mutex: 0,
             cond: 0,
             count: 0,
+            max_count,
         }
     }
 }
@@ -35,7 +38,7 @@ impl fmt::Debug for SharedVars {
     }
 }
 
-fn main() {
+fn main() -> Result<(), Error> {
     env::set_var("RUST_LOG", "info");
     env_logger::builder()
         .format(|buf, record| {
@@ -48,68 +51,73 @@ fn main() {
             )
         })
         .init();
-    let r0 = SharedVars::new();
-    let process_p = Process {
-        0: vec![
-            (
-                String::from("P0"),
-                vec![Trans::new("lock", "P1", is_locked, lock)],
-            ),
-            (
-                String::from("P1"),
-                vec![
-                    Trans::new("wait", "P2", can_wait_p, wait_p),
-                    Trans::new("produce", "P3", can_produce, produce),
-                ],
-            ),
-            (
-                String::from("P2"),
-                vec![Trans::new("wakeup", "P0", can_wakeup_p, wakeup)],
-            ),
-            (
-                String::from("P3"),
-                vec![Trans::new("signal", "P4", always_true, signal)],
-            ),
-            (
-                String::from("P4"),
-                vec![Trans::new("unlock", "P0", always_true, unlock)],
-            ),
-        ],
-    };
-
-    let process_q = Process {
-        0: vec![
-            (
-                String::from("Q0"),
-                vec![Trans::new("lock", "Q1", is_locked, lock)],
-            ),
-            (
-                String::from("Q1"),
-                vec![
-                    Trans::new("wait", "Q2", can_wait_q, wait_q),
-                    Trans::new("consume", "Q3", can_consume, consume),
-                ],
-            ),
-            (
-                String::from("Q2"),
-                vec![Trans::new("wakeup", "Q0", can_wakeup_q, wakeup)],
-            ),
-            (
-                String::from("Q3"),
-                vec![Trans::new("signal", "Q4", always_true, signal)],
-            ),
-            (
-                String::from("Q4"),
-                vec![Trans::new("unlock", "Q0", always_true, unlock)],
-            ),
-        ],
-    };
-
-    process_p.viz_process("m_prod_cons1_P");
-    process_q.viz_process("m_prod_cons1_Q");
-    let lts = data::concurrent_composition(&r0, &vec![process_p, process_q]);
-    data::lts_print_deadlock(&lts);
-    data::viz_lts("m_prod_cons1", &lts);
+    let ps = processes();
+    // バッファの大きさごとの状態空間の大きさ
+    let rows = sweep::sweep(1..=5, |n| Composition::new(&SharedVars::new(*n), &ps))?;
+    sweep::print_sweep(&mut io::stdout(), "MAX_COUNT", &rows)?;
+
+    ps[0].viz_process("m_prod_cons1_P", DotFormat::from_env())?;
+    ps[1].viz_process("m_prod_cons1_Q", DotFormat::from_env())?;
+
+    let lts = data::concurrent_composition(&SharedVars::new(3), &ps)?;
+    data::lts_print_deadlock(&lts)?;
+    data::viz_lts("m_prod_cons1", &lts, DotFormat::from_env())
+}
+
+fn processes() -> Vec<Process<SharedVars>> {
+    let process_p = Process(vec![
+        (
+            String::from("P0"),
+            vec![Trans::new("lock", "P1", is_locked, lock)],
+        ),
+        (
+            String::from("P1"),
+            vec![
+                Trans::new("wait", "P2", can_wait_p, wait_p),
+                Trans::new("produce", "P3", can_produce, produce),
+            ],
+        ),
+        (
+            String::from("P2"),
+            vec![Trans::new("wakeup", "P0", can_wakeup_p, wakeup)],
+        ),
+        (
+            String::from("P3"),
+            vec![Trans::new("signal", "P4", always_true, signal)],
+        ),
+        (
+            String::from("P4"),
+            vec![Trans::new("unlock", "P0", always_true, unlock)],
+        ),
+    ]);
+
+    let process_q = Process(vec![
+        (
+            String::from("Q0"),
+            vec![Trans::new("lock", "Q1", is_locked, lock)],
+        ),
+        (
+            String::from("Q1"),
+            vec![
+                Trans::new("wait", "Q2", can_wait_q, wait_q),
+                Trans::new("consume", "Q3", can_consume, consume),
+            ],
+        ),
+        (
+            String::from("Q2"),
+            vec![Trans::new("wakeup", "Q0", can_wakeup_q, wakeup)],
+        ),
+        (
+            String::from("Q3"),
+            vec![Trans::new("signal", "Q4", always_true, signal)],
+        ),
+        (
+            String::from("Q4"),
+            vec![Trans::new("unlock", "Q0", always_true, unlock)],
+        ),
+    ]);
+
+    vec![process_p, process_q]
 }
 
 // guard
@@ -122,7 +130,7 @@ fn is_locked(r: &SharedVars) -> bool {
 }
 
 fn can_wait_p(r: &SharedVars) -> bool {
-    r.count == MAX_COUNT
+    r.count == r.max_count
 }
 
 fn can_wait_q(r: &SharedVars) -> bool {
@@ -130,7 +138,7 @@ fn can_wait_q(r: &SharedVars) -> bool {
 }
 
 fn can_produce(r: &SharedVars) -> bool {
-    r.count < MAX_COUNT
+    r.count < r.max_count
 }
 
 fn can_consume(r: &SharedVars) -> bool {
@@ -1,21 +1,128 @@
-use log::debug;
+use log::{debug, warn};
 use std::cmp::Eq;
-use std::collections::{HashMap, VecDeque};
+use std::collections::HashSet;
+use std::env;
 use std::fmt;
-use std::fmt::Debug;
+use std::fmt::{Debug, Display};
 use std::fs;
 use std::hash::Hash;
-use std::io::{stdout, BufWriter, Write};
+use std::io::{self, stdout, BufWriter, Write};
 use std::process::Command;
+use std::str::FromStr;
 
-use super::{Action, Guard, Label, Location, Path, State};
+use super::error::Error;
+use super::format::escape;
+use super::model::{explore_with_limit, Model, StateIndex, StateSpace};
+use super::validate::{lint_unreachable, validate, validate_state, ModelError};
+use super::{
+    Action, Check, Choice, Edges, GlobalAction, GlobalGuard, Guard, Label, LocId, Location, NextFn,
+    Packed, Path, State, Successors,
+};
 
 #[derive(Clone)]
 pub struct Trans<T> {
     pub label: Label,
     pub location: Location,
-    pub guard: Guard<T>,
-    pub action: Action<T>,
+    pub guard: Condition<T>,
+    pub action: Effect<T>,
+    /// 不可分な列の途中に入る遷移。合成では遷移先から同じプロセスが続けて遷移する
+    pub atomic: bool,
+}
+
+/// 遷移の条件。`Global` は全プロセスの場所を含む遷移元の状態全体を見る。`Not` は `Local` の否定
+#[derive(Clone)]
+pub enum Condition<T> {
+    Local(Guard<T>),
+    Global(GlobalGuard<T>),
+    Not(Guard<T>),
+}
+
+impl<T> Condition<T> {
+    /// 共有変数が `r` の状態で条件が成り立つか。`state` は大域的な条件のときだけ呼ばれる
+    pub fn holds<F>(&self, r: &T, state: F) -> bool
+    where
+        F: FnOnce() -> State<T>,
+    {
+        match self {
+            Condition::Local(f) => f(r),
+            Condition::Global(f) => f(&state()),
+            Condition::Not(f) => !f(r),
+        }
+    }
+}
+
+/// 遷移による共有変数の変化。`Choice` は非決定的な選択で、返したすべての値が遷移先になる。
+/// `Check` は `Err` を返すと遷移が失敗し、メッセージ付きのエラー状態になる。
+/// `Global` は遷移元の状態全体から遷移後の共有変数を計算する。
+/// `Block` は `Op` の列を割り込みなしに実行する
+#[derive(Clone)]
+pub enum Effect<T> {
+    Action(Action<T>),
+    Choice(Choice<T>),
+    Check(Check<T>),
+    Global(GlobalAction<T>),
+    Block(Vec<Op<T>>),
+}
+
+/// `Effect::Block` の1ステップ。`Assume` の条件が成り立たない分岐はそこで止まり、遷移先にならない
+#[derive(Clone)]
+pub enum Op<T> {
+    Assume(Guard<T>),
+    Action(Action<T>),
+    Choice(Choice<T>),
+    Check(Check<T>),
+    If(Guard<T>, Vec<Op<T>>, Vec<Op<T>>),
+}
+
+impl<T> Op<T>
+where
+    T: Clone,
+{
+    fn apply(&self, r: &T) -> Vec<Result<T, String>> {
+        match self {
+            Op::Assume(f) if f(r) => vec![Ok(r.clone())],
+            Op::Assume(_) => vec![],
+            Op::Action(f) => vec![Ok(f(r))],
+            Op::Choice(f) => f(r).into_iter().map(Ok).collect(),
+            Op::Check(f) => vec![f(r)],
+            Op::If(f, then, otherwise) => run_ops(if f(r) { then } else { otherwise }, r),
+        }
+    }
+}
+
+// `ops` を順に実行する。失敗した分岐はそれ以降を実行しない
+fn run_ops<T>(ops: &[Op<T>], r: &T) -> Vec<Result<T, String>>
+where
+    T: Clone,
+{
+    ops.iter().fold(vec![Ok(r.clone())], |rs, op| {
+        rs.into_iter()
+            .flat_map(|r| match r {
+                Ok(r) => op.apply(&r),
+                Err(e) => vec![Err(e)],
+            })
+            .collect()
+    })
+}
+
+impl<T> Effect<T>
+where
+    T: Clone,
+{
+    /// 遷移後の共有変数をすべて返す。失敗した遷移は `Err` になる。
+    /// `state` は大域的な動作のときだけ呼ばれる
+    pub fn apply<F>(&self, r: &T, state: F) -> Vec<Result<T, String>>
+    where
+        F: FnOnce() -> State<T>,
+    {
+        match self {
+            Effect::Action(f) => vec![Ok(f(r))],
+            Effect::Choice(f) => f(r).into_iter().map(Ok).collect(),
+            Effect::Check(f) => vec![f(r)],
+            Effect::Global(f) => vec![Ok(f(&state()))],
+            Effect::Block(ops) => run_ops(ops, r),
+        }
+    }
 }
 
 impl<T> fmt::Debug for Trans<T> {
@@ -32,8 +139,47 @@ impl<T> Trans<T> {
         Trans {
             label: String::from(label),
             location: String::from(location),
-            guard: guard,
-            action: action,
+            guard: Condition::Local(guard),
+            action: Effect::Action(action),
+            atomic: false,
+        }
+    }
+
+    /// 遷移先の共有変数を複数返す非決定的な遷移。すべて同じラベルの遷移になる
+    pub fn choice(label: &str, location: &str, guard: Guard<T>, choice: Choice<T>) -> Trans<T> {
+        Trans {
+            label: String::from(label),
+            location: String::from(location),
+            guard: Condition::Local(guard),
+            action: Effect::Choice(choice),
+            atomic: false,
+        }
+    }
+
+    /// 失敗しうる遷移。`check` が `Err` を返すとその遷移はエラー状態への遷移になる
+    pub fn check(label: &str, location: &str, guard: Guard<T>, check: Check<T>) -> Trans<T> {
+        Trans {
+            label: String::from(label),
+            location: String::from(location),
+            guard: Condition::Local(guard),
+            action: Effect::Check(check),
+            atomic: false,
+        }
+    }
+
+    /// 他のプロセスの場所も見る遷移。`guard` と `action` には遷移元の状態全体が渡される
+    pub fn global(
+        label: &str,
+        location: &str,
+        guard: GlobalGuard<T>,
+        action: GlobalAction<T>,
+    ) -> Trans<T> {
+        Trans {
+            label: String::from(label),
+            location: String::from(location),
+            guard: Condition::Global(guard),
+            action: Effect::Global(action),
+            atomic: false,
         }
     }
 }
@@ -56,257 +202,1132 @@ where
             .iter()
             .map(move |(label, trans)| (String::from(*label), (*trans).clone()))
             .collect::<Vec<_>>();
-        Process { 0: vv }
+        Process(vv)
     }
 
-    pub fn assoc(&self, location: &str) -> Option<&Vec<Trans<T>>> {
-        for v in &self.0 {
-            if v.0 == location {
-                return Some(v.1.as_ref());
+    /// `locations` の列を不可分にする。列の途中の場所へ前から後ろに進む遷移は、合成では
+    /// 続く遷移と合わせて1つの遷移になり、ラベルは途中のラベルを `"; "` でつないだものになる。
+    /// 途中の状態はLTSに入らないので、トレースにはラベルしか残らない。
+    /// 列の途中で遷移できなくなるときは、列の最初の遷移も実行できない
+    pub fn atomic(mut self, locations: &[&str]) -> Result<Process<T>, Error> {
+        let ids = locations
+            .iter()
+            .map(|l| {
+                self.location_id(l)
+                    .ok_or_else(|| ModelError::UndeclaredAtomicLocation {
+                        location: l.to_string(),
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let inner = 1..locations.len().saturating_sub(1);
+        for (i, id) in ids.iter().enumerate() {
+            for t in self.0[*id as usize].1.iter_mut() {
+                if let Some(j) = locations.iter().position(|l| *l == t.location) {
+                    t.atomic = t.atomic || (j > i && inner.contains(&j));
+                }
             }
         }
-        None
+        Ok(self)
     }
-    pub fn viz_process(&self, filename: &str) {
-        let mut f = BufWriter::new(fs::File::create(format!("{}.dot", filename)).unwrap());
-        f.write("digraph {\n".as_bytes()).unwrap();
 
-        &self
-            .0
+    /// 場所の番号。番号は定義での順番
+    pub fn location_id(&self, location: &str) -> Option<LocId> {
+        self.0
             .iter()
-            .map(|v| {
-                f.write(format!("{};\n", v.0.to_string()).as_bytes())
-                    .unwrap();
-            })
-            .collect::<Vec<_>>();
+            .position(|(l, _)| l == location)
+            .map(|i| i as LocId)
+    }
+
+    /// `id` 番目の場所からの遷移
+    pub fn assoc(&self, id: LocId) -> Option<&Vec<Trans<T>>> {
+        self.0.get(id as usize).map(|(_, trans)| trans)
+    }
+
+    pub fn write_dot(&self, ch: &mut dyn Write) -> Result<(), Error> {
+        ch.write_all("digraph {\n".as_bytes())?;
+
+        for v in &self.0 {
+            ch.write_all(format!("\"{}\";\n", escape(&v.0)).as_bytes())?;
+        }
+
+        for v in &self.0 {
+            for trans in &v.1 {
+                let target = escape(&trans.location);
+                let label = escape(&trans.label);
+                let line = format!(
+                    "\"{}\" -> \"{}\" [label=\"{}\"];\n",
+                    escape(&v.0),
+                    target,
+                    label
+                );
+                ch.write_all(line.as_bytes())?;
+            }
+        }
+
+        ch.write_all("}\n".as_bytes())?;
+        Ok(())
+    }
+
+    /// `<filename>.dot` を書き出し、`format` が指定されていれば `dot` で画像に変換する
+    pub fn viz_process(&self, filename: &str, format: Option<DotFormat>) -> Result<(), Error> {
+        let mut f = BufWriter::new(fs::File::create(format!("{}.dot", filename))?);
+        self.write_dot(&mut f)?;
+        f.flush()?;
+        match format {
+            Some(format) => run_dot(filename, format),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DotFormat {
+    Svg,
+    Png,
+    Pdf,
+}
 
-        &self
-            .0
+impl DotFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DotFormat::Svg => "svg",
+            DotFormat::Png => "png",
+            DotFormat::Pdf => "pdf",
+        }
+    }
+
+    /// 環境変数 `DDSV_DOT_FORMAT` (`svg`、`png`、`pdf`) で指定された形式。
+    /// 指定がなければ `None` で、dotファイルだけを書き出す
+    pub fn from_env() -> Option<DotFormat> {
+        let value = env::var("DDSV_DOT_FORMAT").ok()?;
+        let format = value.parse().ok();
+        if format.is_none() {
+            warn!(
+                "unknown DDSV_DOT_FORMAT `{}`; writing only dot files",
+                value
+            );
+        }
+        format
+    }
+}
+
+impl FromStr for DotFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "svg" => Ok(DotFormat::Svg),
+            "png" => Ok(DotFormat::Png),
+            "pdf" => Ok(DotFormat::Pdf),
+            _ => Err(format!("unknown dot format `{}`", s)),
+        }
+    }
+}
+
+/// `<filename>.dot` を `dot` で `<filename>.<拡張子>` に変換し、終了を待つ
+pub fn run_dot(filename: &str, format: DotFormat) -> Result<(), Error> {
+    let output = Command::new("dot")
+        .arg("-T")
+        .arg(format.extension())
+        .arg("-o")
+        .arg(format!("{}.{}", filename, format.extension()))
+        .arg(format!("{}.dot", filename))
+        .output()
+        .map_err(|e| io::Error::new(e.kind(), format!("failed to run dot: {}", e)))?;
+    if !output.status.success() {
+        return Err(Error::Dot {
+            status: output.status.to_string(),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    debug!("succeed to output {}.{}", filename, format.extension());
+    Ok(())
+}
+
+pub fn make_initial_state<T>(r0: &T, ps: &[Process<T>]) -> Result<State<T>, Error>
+where
+    T: Clone,
+{
+    let v = ps
+        .iter()
+        .enumerate()
+        .map(|(i, p)| match p.0.first() {
+            Some((location, _)) => Ok(location.clone()),
+            None => Err(ModelError::EmptyProcess { process: i }),
+        })
+        .collect::<Result<Vec<String>, _>>()?;
+    Ok((r0.clone(), v))
+}
+
+/// 場所を番号で表した状態を、プロセスごとの場所の名前 `names` を使って名前で表す
+pub fn unpack<T>(names: &[Vec<Location>], state: &Packed<T>) -> State<T>
+where
+    T: Clone,
+{
+    let (r, ids) = state;
+    let locs = names
+        .iter()
+        .zip(ids.iter())
+        .map(|(ns, id)| ns[*id as usize].clone())
+        .collect();
+    (r.clone(), locs)
+}
+
+pub fn unpack_path<T>(names: &[Vec<Location>], path: &[(Label, Packed<T>)]) -> Path<T>
+where
+    T: Clone,
+{
+    path.iter()
+        .map(|(label, state)| (label.clone(), unpack(names, state)))
+        .collect()
+}
+
+/// 場所を番号で表したプロセスの組。場所の番号はプロセスの定義での順番で、
+/// 遷移先の番号は作るときに解決しておく
+#[derive(Clone)]
+pub struct Network<T> {
+    ps: Vec<Process<T>>,
+    names: Vec<Vec<Location>>,
+    // プロセスごと、場所ごと、遷移ごとの遷移先の番号
+    targets: Vec<Vec<Vec<LocId>>>,
+}
+
+impl<T> Network<T>
+where
+    T: Clone,
+{
+    /// モデルを検査して遷移先を番号に解決する。定義に誤りがあればすべての誤りを返す。
+    /// 到達できない場所はログに警告するだけで受け付ける
+    pub fn new(ps: &[Process<T>]) -> Result<Network<T>, Error> {
+        validate(ps)?;
+        for warning in lint_unreachable(ps) {
+            warn!("{}", warning);
+        }
+        let names = ps
             .iter()
-            .map(|v| {
-                v.1.iter().for_each(|trans| {
-                    let target = &trans.location;
-                    let label = &trans.label;
-                    let line = format!("{} -> {} [label=\"{}\"];\n", &v.0, &target, &label);
-                    f.write(&line.as_bytes()).unwrap();
-                });
+            .map(|p| p.0.iter().map(|(l, _)| l.clone()).collect())
+            .collect();
+        let targets = ps
+            .iter()
+            .map(|p| {
+                p.0.iter()
+                    .map(|(_, trans)| {
+                        trans
+                            .iter()
+                            .map(|t| p.location_id(&t.location).unwrap())
+                            .collect()
+                    })
+                    .collect()
             })
-            .collect::<Vec<_>>();
+            .collect();
+        Ok(Network {
+            ps: ps.to_vec(),
+            names,
+            targets,
+        })
+    }
+
+    pub fn processes(&self) -> &[Process<T>] {
+        &self.ps
+    }
+
+    /// プロセスごとの場所の名前
+    pub fn location_names(&self) -> &[Vec<Location>] {
+        &self.names
+    }
 
-        f.write("}\n".as_bytes()).unwrap();
+    /// 共有変数が `r0` で、すべてのプロセスが最初の場所にいる状態
+    pub fn initial_state(&self, r0: &T) -> Packed<T> {
+        (r0.clone(), vec![0; self.ps.len()])
+    }
 
-        Command::new("dot")
-            .arg("-T")
-            .arg("pdf")
-            .arg("-o")
-            .arg(format!("{}.pdf", filename))
-            .arg(format!("{}.dot", filename))
-            .spawn()
-            .expect("failed to visualize");
-        debug!("succeed to output PDF");
+    /// 場所を名前で表した状態を番号で表す
+    pub fn pack(&self, state: &State<T>) -> Result<Packed<T>, Error> {
+        validate_state(&self.ps, state)?;
+        let (r, locs) = state;
+        let ids = self
+            .ps
+            .iter()
+            .zip(locs.iter())
+            .map(|(p, l)| p.location_id(l).unwrap())
+            .collect();
+        Ok((r.clone(), ids))
+    }
+
+    pub fn unpack(&self, state: &Packed<T>) -> State<T> {
+        unpack(&self.names, state)
     }
 }
 
-pub fn make_initial_state<T>(r0: &T, ps: &[Process<T>]) -> State<T>
+/// 状態 `s` でプロセス `k` が実行できる遷移を `buf` の末尾に加える。
+/// 遷移先はプロセスの番号順、同じプロセス内では遷移の定義順、非決定的な遷移では選択肢の順に並ぶ。
+/// 不可分な列の途中に入る遷移は、列の先まで続けて1つの遷移にする
+pub fn push_transitions<T>(buf: &mut Successors<T>, net: &Network<T>, s: &Packed<T>, k: usize)
 where
     T: Clone,
 {
-    let v = ps.iter().map(|p| p.0[0].0.clone()).collect::<Vec<String>>();
-    (r0.clone(), v)
+    push_steps(buf, net, s, k, None)
+}
+
+// `prefix` は不可分な列の途中まで実行した遷移のラベル
+fn push_steps<T>(
+    buf: &mut Successors<T>,
+    net: &Network<T>,
+    s: &Packed<T>,
+    k: usize,
+    prefix: Option<&str>,
+) where
+    T: Clone,
+{
+    let (r, locs) = s;
+    let l = locs[k] as usize;
+    for (trans, target) in net.ps[k].0[l].1.iter().zip(net.targets[k][l].iter()) {
+        // 大域的なガードとアクションのときだけ文字列の状態を作る
+        if !trans.guard.holds(r, || net.unpack(s)) {
+            continue;
+        }
+        let label = match prefix {
+            Some(prefix) => format!("{}; {}", prefix, trans.label),
+            None => trans.label.clone(),
+        };
+        for r_ in trans.action.apply(r, || net.unpack(s)) {
+            // guardが成立 => 遷移可能。k番目のプロセスの場所だけが変わる
+            let target = r_.map(|r_| {
+                let mut locations = locs.clone();
+                locations[k] = *target;
+                (r_, locations)
+            });
+            match target {
+                Ok(next) if trans.atomic => {
+                    // 列は前にしか進まないので再帰は列の長さで止まる。
+                    // 続きを実行できなければ途中で止めずに、列全体を実行できないものとする
+                    push_steps(buf, net, &next, k, Some(&label));
+                }
+                target => buf.push((label.clone(), target)),
+            }
+        }
+    }
 }
 
+/// 状態 `s` でプロセス `k` が実行できる遷移を `acc` に加える
 pub fn calc_transitions<T>(
-    acc: Vec<(Label, State<T>)>,
-    r: &T,
-    rs: &[Location],
-    ls: &[Location],
-    transitions: &[Trans<T>],
-) -> Vec<(Label, State<T>)>
+    mut acc: Successors<T>,
+    net: &Network<T>,
+    s: &Packed<T>,
+    k: usize,
+) -> Successors<T>
 where
     T: Clone,
 {
-    let tmp = transitions.iter().fold(acc, |acc_, trans| {
-        if (trans.guard)(&r) {
-            // guardが成立 => 遷移可能
-            let label = &trans.label; // label = "read"
-            let mut v1 = ls.to_vec(); // ls = (sk, sk+1, ..., sn)
-            v1.insert(0, trans.location.clone()); // location = P1
-
-            let mut locations = rs.to_vec(); // rs = (sk-1, sk-2, ..., s2, s1)
-            locations.reverse();
-            locations.append(&mut v1);
-            // target = (遷移後の共有変数, (s1, s2, ..., sn))
-            let target = ((trans.action)(&r), locations);
-            // t = ("read", (遷移後の共有変数, (s1, s2, ..., sn)))
-            let t = (String::from(label), target);
-            let mut acc__ = acc_.clone();
-            acc__.insert(0, t);
-            acc__
-        } else {
-            acc_
-        }
-    });
-    tmp
-}
-
-pub fn collect_trans<T>(
-    acc: Vec<(Label, State<T>)>,
-    r: &T,
-    rs: &[Location], // (sk-1, sk-2, ..., s2, s1)
-    ls: &[Location], // (sk, sk+1, ..., sn)
-    ps: &[Process<T>],
-) -> Vec<(Label, State<T>)>
+    push_transitions(&mut acc, net, s, k);
+    acc
+}
+
+/// 状態 `s` から実行できるすべての遷移で `buf` を置き換える。`buf` の領域は使い回す
+pub fn collect_trans_into<T>(buf: &mut Successors<T>, net: &Network<T>, s: &Packed<T>)
 where
-    T: Debug + Clone,
+    T: Clone,
+{
+    buf.clear();
+    for k in 0..net.ps.len() {
+        push_transitions(buf, net, s, k);
+    }
+}
+
+/// 状態 `s` から実行できるすべての遷移
+pub fn collect_trans<T>(net: &Network<T>, s: &Packed<T>) -> Successors<T>
+where
+    T: Clone,
+{
+    let mut buf = vec![];
+    collect_trans_into(&mut buf, net, s);
+    buf
+}
+
+pub fn make_next_function<T>(net: Network<T>) -> NextFn<T>
+where
+    T: Clone + 'static,
 {
-    match (ls, ps) {
-        ([], []) => acc,
-        (l, p) => {
-            let (location, ls_2) = l.split_first().unwrap();
-            let (process, ps_2) = p.split_first().unwrap();
-            let transitions = process.assoc(&location).unwrap();
-            let acc = calc_transitions(acc, r, rs, ls_2, transitions);
-            let mut rs_2 = vec![location.clone()];
-            rs_2.extend(rs.to_vec());
-            return collect_trans(acc, r, rs_2.as_slice(), ls_2, ps_2);
+    Box::new(move |s, buf| {
+        collect_trans_into(buf, &net, s);
+        Ok(())
+    })
+}
+
+/// 共有変数の初期値と組にしたプロセスの並行合成
+pub struct Composition<T> {
+    net: Network<T>,
+    r0s: Vec<T>,
+}
+
+impl<T> Composition<T>
+where
+    T: Clone,
+{
+    /// モデルを検査してから組にする。定義に誤りがあればすべての誤りを返す
+    pub fn new(r0: &T, ps: &[Process<T>]) -> Result<Composition<T>, Error> {
+        Composition::from_initials(std::slice::from_ref(r0), ps)
+    }
+
+    /// 共有変数の初期値が `r0s` のいずれかである状態から始める。プロセスはすべて最初の場所にいる
+    pub fn from_initials(r0s: &[T], ps: &[Process<T>]) -> Result<Composition<T>, Error> {
+        Ok(Composition {
+            net: Network::new(ps)?,
+            r0s: r0s.to_vec(),
+        })
+    }
+
+    pub fn network(&self) -> &Network<T> {
+        &self.net
+    }
+}
+
+impl<T> Model for Composition<T>
+where
+    T: Clone,
+{
+    type State = Packed<T>;
+    type Label = Label;
+
+    fn initial_states(&self) -> Vec<Packed<T>> {
+        self.r0s
+            .iter()
+            .map(|r0| self.net.initial_state(r0))
+            .collect()
+    }
+
+    fn successors(&self, s: &Packed<T>, buf: &mut Successors<T>) -> Result<(), Error> {
+        for k in 0..self.net.ps.len() {
+            push_transitions(buf, &self.net, s, k);
         }
+        Ok(())
     }
 }
 
-pub fn make_next_function<T>(ps: Vec<Process<T>>) -> Box<dyn Fn(State<T>) -> Vec<(Label, State<T>)>>
+/// 初期状態と `NextFn` で表したモデル
+struct NextModel<T> {
+    s0: Packed<T>,
+    next: NextFn<T>,
+}
+
+impl<T> Model for NextModel<T>
 where
-    T: Debug + Clone + 'static,
+    T: Clone,
 {
-    Box::new(move |(r, locs)| return collect_trans(vec![], &r, &[], locs.as_slice(), ps.as_slice()))
+    type State = Packed<T>;
+    type Label = Label;
+
+    fn initial_states(&self) -> Vec<Packed<T>> {
+        vec![self.s0.clone()]
+    }
+
+    fn successors(&self, s: &Packed<T>, buf: &mut Successors<T>) -> Result<(), Error> {
+        (self.next)(s, buf)
+    }
 }
 
-pub fn concurrent_composition<T>(
+/// モデルを検査してから合成する。定義に誤りがあれば探索せずにすべての誤りを返す
+pub fn concurrent_composition<T>(r0: &T, ps: &[Process<T>]) -> Result<Lts<T>, Error>
+where
+    T: Debug + Hash + Eq + Clone + 'static,
+{
+    concurrent_composition_with_limit(r0, ps, usize::MAX)
+}
+
+/// 状態数が `max_states` を超えたら探索を打ち切って `Error::LimitExceeded` を返す
+pub fn concurrent_composition_with_limit<T>(
     r0: &T,
     ps: &[Process<T>],
-) -> (HashMap<State<T>, (i32, Path<T>)>, Vec<Path<T>>)
+    max_states: usize,
+) -> Result<Lts<T>, Error>
+where
+    T: Debug + Hash + Eq + Clone + 'static,
+{
+    compose(Composition::new(r0, ps)?, max_states)
+}
+
+/// 共有変数の初期値を `r0s` のいずれかとして合成する。初期状態のIDは `r0s` の順に0から振る
+pub fn concurrent_composition_from<T>(r0s: &[T], ps: &[Process<T>]) -> Result<Lts<T>, Error>
+where
+    T: Debug + Hash + Eq + Clone + 'static,
+{
+    compose(Composition::from_initials(r0s, ps)?, usize::MAX)
+}
+
+fn compose<T>(model: Composition<T>, max_states: usize) -> Result<Lts<T>, Error>
 where
     T: Debug + Hash + Eq + Clone + 'static,
 {
-    let s0 = make_initial_state(r0, ps);
-    let next = make_next_function(ps.to_vec());
-    bfs(s0, next, "---")
+    let names = model.network().location_names().to_vec();
+    let space = explore_with_limit(&model, max_states)?;
+    Ok(Lts::from_space(names, space, "---"))
+}
+
+// 任意のモデルの状態を場所のない状態として、ラベルを文字列として見せる
+struct Plain<'a, M>(&'a M);
+
+impl<M> Model for Plain<'_, M>
+where
+    M: Model,
+    M::Label: Display,
+{
+    type State = Packed<M::State>;
+    type Label = Label;
+
+    fn initial_states(&self) -> Vec<Packed<M::State>> {
+        self.0
+            .initial_states()
+            .into_iter()
+            .map(|s| (s, vec![]))
+            .collect()
+    }
+
+    fn successors(
+        &self,
+        (s, _): &Packed<M::State>,
+        buf: &mut Successors<M::State>,
+    ) -> Result<(), Error> {
+        let mut steps = vec![];
+        self.0.successors(s, &mut steps)?;
+        buf.extend(
+            steps
+                .into_iter()
+                .map(|(label, target)| (label.to_string(), target.map(|t| (t, vec![])))),
+        );
+        Ok(())
+    }
 }
 
+/// 任意の `Model` を探索してLTSにする。状態は場所のない `(状態, [])` で、ラベルは `Display` の文字列。
+/// LTSを受け取る検査 (詳細化、デッドロックの表示、DOTや各形式への出力) がそのまま使える
+pub fn model_lts<M>(model: &M) -> Result<Lts<M::State>, Error>
+where
+    M: Model,
+    M::State: Hash + Eq + Clone,
+    M::Label: Display,
+{
+    model_lts_with_limit(model, usize::MAX)
+}
+
+/// 状態数が `max_states` を超えたら探索を打ち切って `Error::LimitExceeded` を返す
+pub fn model_lts_with_limit<M>(model: &M, max_states: usize) -> Result<Lts<M::State>, Error>
+where
+    M: Model,
+    M::State: Hash + Eq + Clone,
+    M::Label: Display,
+{
+    let space = explore_with_limit(&Plain(model), max_states)?;
+    Ok(Lts::from_space(vec![], space, "---"))
+}
+
+/// `locations` はプロセスごとの場所の名前。トレースを表示するときに使う
 pub fn bfs<T>(
-    s0: State<T>,
-    next: Box<dyn Fn(State<T>) -> Vec<(Label, State<T>)>>,
+    locations: Vec<Vec<Location>>,
+    s0: Packed<T>,
+    next: NextFn<T>,
+    label0: &str,
+) -> Result<Lts<T>, Error>
+where
+    T: Hash + Eq + Debug + Clone,
+{
+    bfs_with_limit(locations, s0, next, label0, usize::MAX)
+}
+
+pub fn bfs_with_limit<T>(
+    locations: Vec<Vec<Location>>,
+    s0: Packed<T>,
+    next: NextFn<T>,
     label0: &str,
-) -> (HashMap<State<T>, (i32, Path<T>)>, Vec<Path<T>>)
+    max_states: usize,
+) -> Result<Lts<T>, Error>
 where
     T: Hash + Eq + Debug + Clone,
 {
-    let mut hm: HashMap<State<T>, (i32, Path<T>)> = HashMap::new();
-    hm.insert(s0.clone(), (0, vec![]));
+    let space = explore_with_limit(&NextModel { s0, next }, max_states)?;
+    Ok(Lts::from_space(locations, space, label0))
+}
 
-    let mut que: VecDeque<(State<T>, i32, Path<T>)> = VecDeque::new();
-    que.push_front((s0.clone(), 0, vec![(label0.to_string(), s0.clone())]));
-    let mut deadlocks: Vec<Path<T>> = vec![];
+/// 状態空間を探索した結果
+#[derive(Debug)]
+pub struct Lts<T> {
+    /// プロセスごとの場所の名前。状態の場所はこの中の番号で表す
+    pub locations: Vec<Vec<Location>>,
+    /// IDごとの状態
+    pub states: Vec<Packed<T>>,
+    /// IDごとの遷移 (ラベル, 遷移先のID)
+    pub edges: Vec<Edges>,
+    /// IDごとの (親のID, 親からの遷移のラベル)。初期状態の親は -1
+    pub parents: Vec<(i32, Label)>,
+    /// 初期状態の数。IDが `0..initials` の状態が初期状態
+    pub initials: usize,
+    /// 遷移先のない状態のID
+    pub deadlocks: Vec<i32>,
+    pub violations: Vec<Violation>,
+    // 状態からIDを引く索引。状態は `states` にだけ持つ
+    index: StateIndex,
+}
 
-    while !que.is_empty() {
-        let (state, id, path) = que.pop_back().unwrap();
-        let trans = (next)(state.clone());
-        if trans.is_empty() {
-            deadlocks.push(path.clone());
+impl<T> Lts<T> {
+    /// `Model` の探索結果から作る。初期状態の親からの遷移のラベルは `label0` にする
+    pub fn from_space(
+        locations: Vec<Vec<Location>>,
+        space: StateSpace<Packed<T>, Label>,
+        label0: &str,
+    ) -> Lts<T>
+    where
+        T: Hash + Eq,
+    {
+        let StateSpace {
+            states,
+            edges,
+            initials,
+            parents,
+            deadlocks,
+            failures,
+            index,
+        } = space;
+        let violations = failures
+            .into_iter()
+            .map(|(state, label, message)| Violation {
+                state,
+                label,
+                message,
+            })
+            .collect();
+        let parents = parents
+            .into_iter()
+            .map(|p| p.unwrap_or_else(|| (-1, label0.to_string())))
+            .collect();
+        Lts {
+            locations,
+            states,
+            edges,
+            parents,
+            initials,
+            deadlocks,
+            violations,
+            index,
         }
+    }
 
-        hm.insert(state, (id, trans.clone()));
-        for (label, target) in &trans {
-            if !hm.contains_key(target) {
-                let id = hm.len() as i32;
-                hm.insert(target.clone(), (id, vec![]));
-                let mut v = path.clone();
-                v.push((label.clone(), target.clone()));
-                que.push_front((target.clone(), id, v));
-            }
+    pub fn is_initial(&self, id: i32) -> bool {
+        0 <= id && (id as usize) < self.initials
+    }
+
+    /// 場所の番号を名前に直す
+    pub fn location_names(&self, ids: &[LocId]) -> Vec<Location> {
+        self.locations
+            .iter()
+            .zip(ids.iter())
+            .map(|(ns, id)| ns[*id as usize].clone())
+            .collect()
+    }
+
+    pub fn unpack(&self, state: &Packed<T>) -> State<T>
+    where
+        T: Clone,
+    {
+        unpack(&self.locations, state)
+    }
+
+    /// 場所を名前で表した状態のID。LTSにない状態なら `None`
+    pub fn id_of(&self, state: &State<T>) -> Option<i32>
+    where
+        T: Hash + Eq + Clone,
+    {
+        let (r, locs) = state;
+        if locs.len() != self.locations.len() {
+            return None;
+        }
+        let ids = self
+            .locations
+            .iter()
+            .zip(locs.iter())
+            .map(|(ns, l)| ns.iter().position(|n| n == l).map(|i| i as LocId))
+            .collect::<Option<Vec<_>>>()?;
+        self.index.get(&self.states, &(r.clone(), ids))
+    }
+
+    /// 初期状態から `id` の状態までの最短の (ラベル, ID) の列。先頭は初期状態
+    pub fn steps_to(&self, id: i32) -> Option<Vec<(&Label, i32)>> {
+        if id < 0 || id as usize >= self.parents.len() {
+            return None;
+        }
+        let mut steps = vec![];
+        let mut id = id;
+        while id >= 0 {
+            let (parent, label) = &self.parents[id as usize];
+            steps.push((label, id));
+            id = *parent;
         }
+        steps.reverse();
+        Some(steps)
+    }
+
+    /// 初期状態から `id` の状態までの最短のトレース
+    pub fn trace_to(&self, id: i32) -> Option<Path<T>>
+    where
+        T: Clone,
+    {
+        self.traces_to(&[id]).pop()
+    }
+
+    /// `ids` の各状態への最短のトレース。存在しないIDは飛ばす
+    pub fn traces_to(&self, ids: &[i32]) -> Vec<Path<T>>
+    where
+        T: Clone,
+    {
+        ids.iter()
+            .filter_map(|id| self.steps_to(*id))
+            .map(|steps| {
+                steps
+                    .into_iter()
+                    .map(|(label, id)| (label.clone(), self.unpack(&self.states[id as usize])))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+/// 遷移の失敗。`state` は失敗した遷移の遷移元のID
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub state: i32,
+    pub label: Label,
+    pub message: String,
+}
+
+/// LTSの状態を (状態, ID, 遷移) の組としてID順に並べる。
+/// 出力はすべてこの順序に従うので、同じモデルからは常に同じ内容が得られる
+pub fn lts_states<T>(lts: &Lts<T>) -> impl Iterator<Item = (&Packed<T>, i32, &Edges)> + '_ {
+    lts.states
+        .iter()
+        .zip(lts.edges.iter())
+        .enumerate()
+        .map(|(id, (state, trans))| (state, id as i32, trans))
+}
+
+pub fn lts_print_deadlock<T>(lts: &Lts<T>) -> Result<(), Error>
+where
+    T: Debug + Clone,
+{
+    for dl in lts.traces_to(&lts.deadlocks) {
+        println!("--------------------------------------");
+        print_deadlock(&dl)?;
+    }
+    Ok(())
+}
+
+/// デッドロックの状態 (失敗する遷移もない状態) のID
+pub fn lts_deadlock_ids<T>(lts: &Lts<T>) -> HashSet<i32> {
+    lts.deadlocks.iter().copied().collect()
+}
+
+/// 遷移の失敗を、失敗した遷移までのトレースと一緒に出力する
+pub fn lts_print_violations<T>(lts: &Lts<T>) -> Result<(), Error>
+where
+    T: Debug + Clone,
+{
+    let ids = lts.violations.iter().map(|v| v.state).collect::<Vec<_>>();
+    for (path, v) in lts.traces_to(&ids).iter().zip(lts.violations.iter()) {
+        println!("!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!!");
+        print_violation(path, v)?;
     }
-    (hm, deadlocks)
+    Ok(())
 }
 
-pub fn lts_print_deadlock<T>(lts: &(HashMap<State<T>, (i32, Path<T>)>, Vec<Path<T>>))
+/// `path` は失敗した遷移の遷移元までのトレース
+pub fn print_violation<T>(path: &Path<T>, violation: &Violation) -> Result<(), Error>
 where
     T: Debug,
 {
-    let (_, deadlock) = lts;
+    print_deadlock(path)?;
+    println!(
+        "{} {:010} error: {}",
+        path.len(),
+        violation.label,
+        violation.message
+    );
+    Ok(())
+}
+
+/// 遷移先のない状態のうち、正常終了とみなすもの
+pub enum Termination<T> {
+    /// プロセスごとの終了場所。すべてのプロセスが終了場所にいれば正常終了
+    EndLocations(Vec<Vec<Location>>),
+    /// 正常終了とみなす大域状態の条件
+    Accepting(fn(&State<T>) -> bool),
+}
+
+impl<T> Termination<T> {
+    pub fn end_locations(ends: &[&[&str]]) -> Termination<T> {
+        Termination::EndLocations(
+            ends.iter()
+                .map(|ls| ls.iter().map(|l| String::from(*l)).collect())
+                .collect(),
+        )
+    }
+
+    pub fn is_terminal(&self, state: &State<T>) -> bool {
+        match self {
+            Termination::EndLocations(ends) => {
+                ends.len() == state.1.len()
+                    && ends
+                        .iter()
+                        .zip(state.1.iter())
+                        .all(|(ls, l)| ls.contains(l))
+            }
+            Termination::Accepting(f) => f(state),
+        }
+    }
+}
+
+/// 遷移先のない状態へのトレースを (正常終了, デッドロック) に分ける
+pub fn lts_split_deadlocks<T>(
+    lts: &Lts<T>,
+    termination: &Termination<T>,
+) -> (Vec<Path<T>>, Vec<Path<T>>)
+where
+    T: Clone,
+{
+    lts.traces_to(&lts.deadlocks)
+        .into_iter()
+        .partition(|dl| termination.is_terminal(&dl.last().unwrap().1))
+}
+
+/// 正常終了とデッドロックを分けて出力する
+pub fn lts_print_deadlock_with<T>(lts: &Lts<T>, termination: &Termination<T>) -> Result<(), Error>
+where
+    T: Debug + Clone,
+{
+    let (terminated, deadlock) = lts_split_deadlocks(lts, termination);
+    for dl in terminated {
+        println!("============== terminated ============");
+        print_deadlock(&dl)?;
+    }
     for dl in deadlock {
         println!("--------------------------------------");
-        print_deadlock(dl);
+        print_deadlock(&dl)?;
     }
+    Ok(())
 }
 
-pub fn print_deadlock<T>(deadlock: &Path<T>)
+pub fn print_deadlock<T>(deadlock: &Path<T>) -> Result<(), Error>
 where
     T: Debug,
 {
     let out = stdout();
     let mut out = BufWriter::new(out.lock());
     for (i, dl) in deadlock.iter().enumerate() {
-        out.write(format!("{} {:010} {:?} ", i, dl.0, (dl.1).0).as_bytes())
-            .unwrap();
-        print_locations(&mut out, (dl.1).1.as_slice());
-        out.write("\n".as_bytes()).unwrap();
+        out.write_all(format_step(i, &dl.0, &dl.1).as_bytes())?;
+        out.write_all("\n".as_bytes())?;
     }
+    out.flush()?;
+    Ok(())
 }
 
-pub fn print_locations(ch: &mut dyn Write, locations: &[Location]) {
+/// `print_deadlock` が出力するトレースの1行 (改行なし)
+pub fn format_step<T>(i: usize, label: &str, state: &State<T>) -> String
+where
+    T: Debug,
+{
+    let (r, locs) = state;
+    let mut line = format!("{} {:010} {:?} ", i, label, r);
+    for l in locs {
+        line.push_str(&format!("{} ", l));
+    }
+    line
+}
+
+pub fn print_locations(ch: &mut dyn Write, locations: &[Location]) -> Result<(), Error> {
     for l in locations {
-        ch.write(format!("{} ", l).as_bytes()).unwrap();
+        ch.write_all(format!("{} ", l).as_bytes())?;
     }
+    Ok(())
+}
+
+/// LTSのDOT出力の設定
+pub struct DotOptions<T> {
+    /// 太い赤線で強調するパス (デッドロックへのトレースなど)
+    pub highlight: Option<Path<T>>,
+    /// 指定した番号のプロセスの場所ごとに状態をクラスタにまとめる。プロセス数以上ならエラー
+    pub cluster_by: Option<usize>,
+    /// `rankdir` の値 ("LR", "TB" など)
+    pub rankdir: Option<String>,
+    /// 共有変数は初期状態にだけ全体を表示し、遷移には変化した部分だけを表示する。
+    /// `Debug` の表示を、derive した構造体ならフィールドごとに、そうでなければ空白区切りで比べる
+    pub diff_edges: bool,
+    /// 出力する状態数の上限。IDの小さい (初期状態に近い) 順に残す
+    pub max_nodes: Option<usize>,
+    /// 遷移先のない状態のうち正常終了とみなすもの。デッドロックとは別の色で塗る
+    pub termination: Option<Termination<T>>,
 }
 
-pub fn viz_lts<T>(filename: &str, lts: &(HashMap<State<T>, (i32, Path<T>)>, Vec<Path<T>>))
+impl<T> Default for DotOptions<T> {
+    fn default() -> Self {
+        DotOptions {
+            highlight: None,
+            cluster_by: None,
+            rankdir: None,
+            diff_edges: false,
+            max_nodes: None,
+            termination: None,
+        }
+    }
+}
+
+pub fn write_lts_dot<T>(ch: &mut dyn Write, lts: &Lts<T>) -> Result<(), Error>
 where
-    T: Debug + Hash + Eq,
+    T: Debug + Hash + Eq + Clone,
 {
-    let (ht, _) = lts;
-    let mut f = BufWriter::new(fs::File::create(format!("{}.dot", filename)).unwrap());
-    f.write("digraph{\n".as_bytes()).unwrap();
-    emit_states(&mut f, &ht);
-    emit_transitions(&mut f, &ht);
-    f.write("}\n".as_bytes()).unwrap();
+    write_lts_dot_with(ch, lts, &DotOptions::default())
+}
 
-    Command::new("dot")
-        .arg("-T")
-        .arg("pdf")
-        .arg("-o")
-        .arg(format!("{}.pdf", filename))
-        .arg(format!("{}.dot", filename))
-        .spawn()
-        .expect("failed to visualize");
+pub fn write_lts_dot_with<T>(
+    ch: &mut dyn Write,
+    lts: &Lts<T>,
+    opts: &DotOptions<T>,
+) -> Result<(), Error>
+where
+    T: Debug + Hash + Eq + Clone,
+{
+    if let Some(k) = opts.cluster_by.filter(|k| *k >= lts.locations.len()) {
+        return Err(Error::InvalidOption(format!(
+            "cluster_by {} is out of range for {} processes",
+            k,
+            lts.locations.len()
+        )));
+    }
+    let shown = opts.max_nodes.unwrap_or(usize::MAX);
+    let states = lts_states(lts)
+        .filter(|(_, id, _)| (*id as usize) < shown)
+        .collect::<Vec<_>>();
+    let path = opts
+        .highlight
+        .iter()
+        .flatten()
+        .filter_map(|(label, state)| lts.id_of(state).map(|id| (label, id)))
+        .collect::<Vec<_>>();
+    let nodes = path.iter().map(|(_, id)| *id).collect::<HashSet<_>>();
+    let edges = path
+        .windows(2)
+        .map(|w| (w[0].1, w[1].1, w[1].0))
+        .collect::<HashSet<_>>();
+    let deadlocks = lts_deadlock_ids(lts);
+
+    ch.write_all("digraph{\n".as_bytes())?;
+    if let Some(rankdir) = &opts.rankdir {
+        ch.write_all(format!("rankdir={};\n", rankdir).as_bytes())?;
+    }
+    match opts.cluster_by {
+        None => emit_states(ch, lts, &states, opts, &deadlocks, &nodes)?,
+        Some(k) => {
+            let mut clusters: Vec<(LocId, Vec<_>)> = vec![];
+            for s in &states {
+                let loc = ((s.0).1)[k];
+                match clusters.iter_mut().find(|(l, _)| *l == loc) {
+                    Some((_, v)) => v.push(*s),
+                    None => clusters.push((loc, vec![*s])),
+                }
+            }
+            for (i, (loc, group)) in clusters.iter().enumerate() {
+                let loc = &lts.locations[k][*loc as usize];
+                ch.write_all(
+                    format!("subgraph cluster_{} {{\nlabel=\"{}\";\n", i, escape(loc)).as_bytes(),
+                )?;
+                emit_states(ch, lts, group, opts, &deadlocks, &nodes)?;
+                ch.write_all("}\n".as_bytes())?;
+            }
+        }
+    }
+    emit_transitions(ch, lts, &states, shown, opts.diff_edges, &edges)?;
+    // 遷移の失敗はエラー状態として別の色で描く
+    for (i, v) in lts.violations.iter().enumerate() {
+        let id = v.state;
+        if id as usize >= shown {
+            continue;
+        }
+        ch.write_all(
+            format!(
+                "e{} [label=\"{}\",shape=box,style=filled,fillcolor=orange];\n",
+                i,
+                escape(&v.message)
+            )
+            .as_bytes(),
+        )?;
+        ch.write_all(format!("{} -> e{} [label=\"{}\"];\n", id, i, escape(&v.label)).as_bytes())?;
+    }
+    if states.len() < lts.states.len() {
+        ch.write_all(
+            format!("// {} of {} states shown\n", states.len(), lts.states.len()).as_bytes(),
+        )?;
+    }
+    ch.write_all("}\n".as_bytes())?;
+    Ok(())
 }
 
-fn emit_states<T>(ch: &mut dyn Write, hm: &HashMap<State<T>, (i32, Path<T>)>)
+/// `<filename>.dot` を書き出し、`format` が指定されていれば `dot` で画像に変換する
+pub fn viz_lts<T>(filename: &str, lts: &Lts<T>, format: Option<DotFormat>) -> Result<(), Error>
 where
-    T: Debug,
+    T: Debug + Hash + Eq + Clone,
+{
+    viz_lts_with(filename, lts, &DotOptions::default(), format)
+}
+
+pub fn viz_lts_with<T>(
+    filename: &str,
+    lts: &Lts<T>,
+    opts: &DotOptions<T>,
+    format: Option<DotFormat>,
+) -> Result<(), Error>
+where
+    T: Debug + Hash + Eq + Clone,
+{
+    let mut f = BufWriter::new(fs::File::create(format!("{}.dot", filename))?);
+    write_lts_dot_with(&mut f, lts, opts)?;
+    f.flush()?;
+    match format {
+        Some(format) => run_dot(filename, format),
+        None => Ok(()),
+    }
+}
+
+fn emit_states<T>(
+    ch: &mut dyn Write,
+    lts: &Lts<T>,
+    states: &[(&Packed<T>, i32, &Edges)],
+    opts: &DotOptions<T>,
+    deadlocks: &HashSet<i32>,
+    highlight: &HashSet<i32>,
+) -> Result<(), Error>
+where
+    T: Debug + Clone,
 {
-    for ((r, locs), (id, trans)) in hm.iter() {
-        ch.write(format!("{} [label=\"{}\\n", id, id).as_bytes())
-            .unwrap();
-        print_locations(ch, locs);
-        ch.write(format!("\\n{:?}\",", r).as_bytes()).unwrap();
-        if *id == 0 {
-            ch.write("style=filled,fillcolor=cyan".as_bytes()).unwrap();
-        } else if trans.len() == 0 {
-            ch.write("style=filled,fillcolor=pink".as_bytes()).unwrap();
+    for (state, id, _) in states {
+        let (r, locs) = state;
+        let mut text = id.to_string();
+        // 場所のないLTS (`model_lts` など) では場所の行を省く
+        if !locs.is_empty() {
+            let mut line = vec![];
+            print_locations(&mut line, &lts.location_names(locs))?;
+            text = format!("{}\\n{}", text, escape(&String::from_utf8_lossy(&line)));
         }
-        ch.write("];\n".as_bytes()).unwrap();
+        if !opts.diff_edges || lts.is_initial(*id) {
+            text = format!("{}\\n{}", text, escape(&format!("{:?}", r)));
+        }
+        ch.write_all(format!("{} [label=\"{}\",", id, text).as_bytes())?;
+        let mut attrs = vec![];
+        if lts.is_initial(*id) {
+            attrs.push("style=filled,fillcolor=cyan");
+        } else if deadlocks.contains(id) {
+            match &opts.termination {
+                Some(t) if t.is_terminal(&lts.unpack(state)) => {
+                    attrs.push("style=filled,fillcolor=palegreen")
+                }
+                _ => attrs.push("style=filled,fillcolor=pink"),
+            }
+        }
+        if highlight.contains(id) {
+            attrs.push("color=red,penwidth=3");
+        }
+        ch.write_all(attrs.join(",").as_bytes())?;
+        ch.write_all("];\n".as_bytes())?;
     }
+    Ok(())
 }
 
-fn emit_transitions<T>(ch: &mut dyn Write, hm: &HashMap<State<T>, (i32, Path<T>)>)
+// `Debug` の表示をいちばん外側の項目に分ける。`#[derive(Debug)]` の構造体 `S { a: 1, b: [2, 3] }`
+// とタプル構造体 `S(1, 2)` は括弧の中をカンマで、それ以外 (`x=1 y=2` のような手書きの表示) は空白で区切る。
+// 入れ子の括弧と文字列の中では区切らない
+fn debug_fields(text: &str) -> Vec<&str> {
+    let text = text.trim();
+    let name = text.find(['{', '(']).map(|i| (i, text[..i].trim()));
+    let (body, sep) = match name {
+        Some((i, name))
+            if (text.ends_with('}') || text.ends_with(')'))
+                && name.chars().all(|c| c.is_alphanumeric() || c == '_') =>
+        {
+            (&text[i + 1..text.len() - 1], ',')
+        }
+        _ => (text, ' '),
+    };
+    let mut fields = vec![];
+    let (mut depth, mut quoted, mut escaped, mut start) = (0, false, false, 0);
+    for (i, c) in body.char_indices() {
+        if quoted {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => quoted = true,
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth -= 1,
+            _ if c == sep && depth == 0 => {
+                fields.push(body[start..i].trim());
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    fields.push(body[start..].trim());
+    fields.retain(|f| !f.is_empty());
+    fields
+}
+
+// 共有変数の `Debug` の表示を項目ごとに比べ、変化した項目だけを取り出す。
+// 項目の数が違えば遷移先の表示全体を返す
+fn diff_vars<T>(from: &T, to: &T) -> String
+where
+    T: Debug,
+{
+    let (from, to) = (format!("{:?}", from), format!("{:?}", to));
+    let (xs, ys) = (debug_fields(&from), debug_fields(&to));
+    if xs.len() != ys.len() {
+        return to;
+    }
+    xs.iter()
+        .zip(ys.iter())
+        .filter(|(x, y)| x != y)
+        .map(|(_, y)| *y)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn emit_transitions<T>(
+    ch: &mut dyn Write,
+    lts: &Lts<T>,
+    states: &[(&Packed<T>, i32, &Edges)],
+    shown: usize,
+    diff_edges: bool,
+    highlight: &HashSet<(i32, i32, &Label)>,
+) -> Result<(), Error>
 where
-    T: Debug + Hash + Eq,
+    T: Debug,
 {
-    for (_key, (id, trans)) in hm.iter() {
-        for (label, target) in trans {
-            let (tid, _) = hm.get(target).unwrap();
-            ch.write(format!("{} -> {} [label=\"{}\"];\n", id, tid, label).as_bytes())
-                .unwrap();
+    for ((r, _), id, trans) in states {
+        for (label, tid) in trans.iter() {
+            if *tid as usize >= shown {
+                continue;
+            }
+            let mut text = escape(label);
+            if diff_edges {
+                let diff = diff_vars(r, &lts.states[*tid as usize].0);
+                if !diff.is_empty() {
+                    text = format!("{}\\n{}", text, escape(&diff));
+                }
+            }
+            let style = if highlight.contains(&(*id, *tid, label)) {
+                ",color=red,penwidth=3"
+            } else {
+                ""
+            };
+            ch.write_all(format!("{} -> {} [label=\"{}\"{}];\n", id, tid, text, style).as_bytes())?;
         }
     }
+    Ok(())
 }
@@ -0,0 +1,67 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use super::validate::ModelError;
+
+/// ddsvの公開関数が返すエラー
+#[derive(Debug)]
+pub enum Error {
+    /// モデルの定義の誤り
+    Model(Vec<ModelError>),
+    /// 探索した状態数が上限を超えた
+    LimitExceeded { limit: usize },
+    /// 入出力の失敗
+    Io(io::Error),
+    /// `dot` が異常終了した
+    Dot { status: String, stderr: String },
+    /// 読み込んだファイルの `line` 行目 (1から数える) の書式の誤り
+    Parse { line: usize, message: String },
+    /// 出力の設定の誤り
+    InvalidOption(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Error::Model(errors) => {
+                let msgs = errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                write!(f, "malformed model: {}", msgs.join("; "))
+            }
+            Error::LimitExceeded { limit } => {
+                write!(f, "state space exceeds the limit of {} states", limit)
+            }
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Dot { status, stderr } => write!(f, "dot exited with {}: {}", status, stderr),
+            Error::Parse { line, message } => write!(f, "line {}: {}", line, message),
+            Error::InvalidOption(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<Vec<ModelError>> for Error {
+    fn from(errors: Vec<ModelError>) -> Self {
+        Error::Model(errors)
+    }
+}
+
+impl From<ModelError> for Error {
+    fn from(e: ModelError) -> Self {
+        Error::Model(vec![e])
+    }
+}
@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::{BufRead, Write};
+
+use super::data::{bfs, lts_states, Lts};
+use super::error::Error;
+use super::{Label, Packed, Successors};
+
+// 状態IDの順に並べた遷移の一覧 (from, label, to)
+fn edges<T>(lts: &Lts<T>) -> Vec<(usize, &Label, usize)>
+where
+    T: Hash + Eq,
+{
+    lts_states(lts)
+        .flat_map(|(_, id, trans)| {
+            trans
+                .iter()
+                .map(move |(label, target)| (id as usize, label, *target as usize))
+        })
+        .collect()
+}
+
+fn invalid(line: usize, msg: &str) -> Error {
+    Error::Parse {
+        line: line + 1,
+        message: msg.to_string(),
+    }
+}
+
+fn parse_state(line: usize, s: &str) -> Result<usize, Error> {
+    s.trim()
+        .parse::<usize>()
+        .map_err(|_| invalid(line, &format!("invalid state number `{}`", s.trim())))
+}
+
+// ラベルの `\`、`"`、タブ、改行をバックスラッシュでエスケープする。
+// DOTの `"..."` の中にもそのまま書けるので、DOTの出力もすべてこれを使う
+pub(crate) fn escape(label: &str) -> String {
+    let mut out = String::with_capacity(label.len());
+    for c in label.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape(line: usize, label: &str) -> Result<String, Error> {
+    let mut out = String::with_capacity(label.len());
+    let mut chars = label.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('"') => out.push('"'),
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            _ => return Err(invalid(line, "invalid escape in label")),
+        }
+    }
+    Ok(out)
+}
+
+/// 読み込んだ遷移からLTSを構築する。状態は初期状態から到達可能なものだけが残る
+fn build(initial: usize, edges: Vec<(usize, Label, usize)>) -> Result<Lts<usize>, Error> {
+    let mut adj: HashMap<usize, Vec<(Label, usize)>> = HashMap::new();
+    for (from, label, to) in edges {
+        adj.entry(from).or_default().push((label, to));
+    }
+    let next = Box::new(move |(s, _): &Packed<usize>, buf: &mut Successors<usize>| {
+        if let Some(v) = adj.get(s) {
+            buf.extend(
+                v.iter()
+                    .map(|(label, to)| (label.clone(), Ok((*to, vec![])))),
+            );
+        }
+        Ok(())
+    });
+    bfs(vec![], (initial, vec![]), next, "---")
+}
+
+// どちらの形式も初期状態を1つしか表せない
+fn single_initial<T>(lts: &Lts<T>) -> Result<(), Error> {
+    if lts.initials == 1 {
+        Ok(())
+    } else {
+        Err(Error::InvalidOption(format!(
+            "the format supports a single initial state, but the LTS has {}",
+            lts.initials
+        )))
+    }
+}
+
+/// Aldebaran形式 (.aut) でLTSを書き出す。初期状態が1つでなければエラー
+pub fn write_aut<T>(ch: &mut dyn Write, lts: &Lts<T>) -> Result<(), Error>
+where
+    T: Hash + Eq,
+{
+    single_initial(lts)?;
+    let edges = edges(lts);
+    writeln!(ch, "des (0, {}, {})", edges.len(), lts.states.len())?;
+    for (from, label, to) in edges {
+        writeln!(ch, "({}, \"{}\", {})", from, escape(label), to)?;
+    }
+    Ok(())
+}
+
+/// Aldebaran形式 (.aut) のLTSを読み込む。状態番号がそのまま共有変数の値になる
+pub fn read_aut(r: &mut dyn BufRead) -> Result<Lts<usize>, Error> {
+    let mut initial = None;
+    let mut edges = vec![];
+    for (i, line) in r.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let body = line
+            .strip_prefix("des")
+            .unwrap_or(line)
+            .trim()
+            .strip_prefix('(')
+            .and_then(|s| s.strip_suffix(')'))
+            .ok_or_else(|| invalid(i, "expected a parenthesized tuple"))?;
+        let first = body.find(',').ok_or_else(|| invalid(i, "missing `,`"))?;
+        let last = body.rfind(',').ok_or_else(|| invalid(i, "missing `,`"))?;
+        if first == last {
+            return Err(invalid(i, "expected three fields"));
+        }
+        let (a, b, c) = (&body[..first], &body[first + 1..last], &body[last + 1..]);
+        if initial.is_none() {
+            if !line.starts_with("des") {
+                return Err(invalid(i, "missing `des` header"));
+            }
+            initial = Some(parse_state(i, a)?);
+            continue;
+        }
+        let label = b.trim();
+        let label = match label.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+            Some(quoted) => unescape(i, quoted)?,
+            None => label.to_string(),
+        };
+        edges.push((parse_state(i, a)?, label, parse_state(i, c)?));
+    }
+    let initial = initial.ok_or_else(|| invalid(0, "missing `des` header"))?;
+    build(initial, edges)
+}
+
+/// 1行に1遷移をタブ区切り (from, label, to) で書き出す。初期状態は0で、1つでなければエラー。
+/// ラベルのタブと改行は `\t`、`\n` にエスケープする
+pub fn write_edge_list<T>(ch: &mut dyn Write, lts: &Lts<T>) -> Result<(), Error>
+where
+    T: Hash + Eq,
+{
+    single_initial(lts)?;
+    for (from, label, to) in edges(lts) {
+        writeln!(ch, "{}\t{}\t{}", from, escape(label), to)?;
+    }
+    Ok(())
+}
+
+/// `write_edge_list` の形式を読み込む。空行と `#` で始まる行は無視する
+pub fn read_edge_list(r: &mut dyn BufRead) -> Result<Lts<usize>, Error> {
+    let mut edges = vec![];
+    for (i, line) in r.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields = line.split('\t').collect::<Vec<_>>();
+        if fields.len() != 3 {
+            return Err(invalid(i, "expected `from<TAB>label<TAB>to`"));
+        }
+        edges.push((
+            parse_state(i, fields[0])?,
+            unescape(i, fields[1])?,
+            parse_state(i, fields[2])?,
+        ));
+    }
+    build(0, edges)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddsv::data::{concurrent_composition_from, Process};
+
+    const AUT: &str = "des (0, 4, 4)\n\
+                       (0, \"lock 0\", 1)\n\
+                       (0, \"lock 1\", 2)\n\
+                       (1, unlock, 0)\n\
+                       (2, \"lock 0\", 3)\n";
+
+    #[test]
+    fn read_aut_test() {
+        let lts = read_aut(&mut AUT.as_bytes()).unwrap();
+        assert_eq!(lts.states.len(), 4);
+        assert_eq!(lts.deadlocks.len(), 1);
+        let dl = lts.trace_to(lts.deadlocks[0]).unwrap();
+        assert_eq!(dl.last().unwrap().0, "lock 0");
+        assert_eq!((dl.last().unwrap().1).0, 3);
+    }
+
+    #[test]
+    fn aut_round_trip_test() {
+        let lts = read_aut(&mut AUT.as_bytes()).unwrap();
+        let mut out = vec![];
+        write_aut(&mut out, &lts).unwrap();
+        let lts_2 = read_aut(&mut out.as_slice()).unwrap();
+        assert_eq!(lts_2.states.len(), lts.states.len());
+        assert_eq!(edges(&lts_2), edges(&lts));
+    }
+
+    #[test]
+    fn edge_list_round_trip_test() {
+        let lts = read_aut(&mut AUT.as_bytes()).unwrap();
+        let mut out = vec![];
+        write_edge_list(&mut out, &lts).unwrap();
+        let lts_2 = read_edge_list(&mut out.as_slice()).unwrap();
+        assert_eq!(edges(&lts_2), edges(&lts));
+    }
+
+    #[test]
+    fn escape_round_trip_test() {
+        let aut = "des (0, 2, 3)\n\
+                   (0, \"say \\\"hi\\\"\\t\\\\\", 1)\n\
+                   (1, \"a, b\", 2)\n";
+        let lts = read_aut(&mut aut.as_bytes()).unwrap();
+        let labels = |lts: &Lts<usize>| {
+            edges(lts)
+                .into_iter()
+                .map(|(_, l, _)| l.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(labels(&lts), ["say \"hi\"\t\\", "a, b"]);
+
+        let mut out = vec![];
+        write_aut(&mut out, &lts).unwrap();
+        assert_eq!(String::from_utf8(out.clone()).unwrap(), aut);
+        assert_eq!(
+            labels(&read_aut(&mut out.as_slice()).unwrap()),
+            labels(&lts)
+        );
+
+        let mut out = vec![];
+        write_edge_list(&mut out, &lts).unwrap();
+        assert_eq!(
+            labels(&read_edge_list(&mut out.as_slice()).unwrap()),
+            labels(&lts)
+        );
+
+        let err = read_aut(&mut "des (0, 1, 2)\n(0, \"a\\x\", 1)\n".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "line 2: invalid escape in label");
+    }
+
+    #[test]
+    fn multiple_initials_test() {
+        let lts =
+            concurrent_composition_from(&[0, 1], &[Process::new(vec![("P0", vec![])])]).unwrap();
+        let err = write_aut(&mut vec![], &lts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the format supports a single initial state, but the LTS has 2"
+        );
+        assert!(write_edge_list(&mut vec![], &lts).is_err());
+    }
+
+    #[test]
+    fn read_aut_error_test() {
+        let err = read_aut(&mut "des (0, 1, 2)\n(0, a)\n".as_bytes()).unwrap_err();
+        assert!(matches!(err, Error::Parse { line: 2, .. }));
+        assert_eq!(err.to_string(), "line 2: expected three fields");
+    }
+}
@@ -0,0 +1,281 @@
+// ロックの獲得順序の解析。探索したLTSの遷移から「A を持ったまま B を獲得した」という辺を
+// プロセスごとに集め、辺の閉路をデッドロックの可能性として報告する。
+// 閉路はデッドロックの状態に到達していなくても見つかる。辺はプロセスごとに集めるので、
+// `Model` ではなくプロセスの並行合成のLTSだけを扱う
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Debug;
+
+use super::data::{lts_states, print_deadlock, Lts};
+use super::error::Error;
+use super::race::Lock;
+use super::Label;
+
+/// プロセス `process` が `held` を持ったまま、状態 `state` からの遷移 `label` で `acquired` を獲得した
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockEdge {
+    pub held: String,
+    pub acquired: String,
+    pub process: usize,
+    pub label: Label,
+    pub state: i32,
+}
+
+impl fmt::Display for LockEdge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "process {} acquires {} holding {} by \"{}\"",
+            self.process, self.acquired, self.held, self.label
+        )
+    }
+}
+
+/// `lts` の遷移からロックの獲得順序の辺を集める。同じロックの組・プロセス・ラベルの辺は
+/// 最初に見つかった状態のものだけを返す
+pub fn lock_order_edges<T>(lts: &Lts<T>, locks: &[Lock<T>]) -> Vec<LockEdge> {
+    let mut edges = vec![];
+    let mut found = HashSet::new();
+    for ((r, locs), id, trans) in lts_states(lts) {
+        for (label, target) in trans.iter() {
+            let (r_, _) = &lts.states[*target as usize];
+            for k in 0..locs.len() {
+                for b in locks.iter().filter(|b| !(b.held)(r, k) && (b.held)(r_, k)) {
+                    for a in locks.iter().filter(|a| (a.held)(r, k)) {
+                        if !found.insert((&a.name, &b.name, k, label)) {
+                            continue;
+                        }
+                        edges.push(LockEdge {
+                            held: a.name.clone(),
+                            acquired: b.name.clone(),
+                            process: k,
+                            label: label.clone(),
+                            state: id,
+                        });
+                    }
+                }
+            }
+        }
+    }
+    edges
+}
+
+/// 閉路の1段。プロセス `process` が `held` を持ったまま `acquired` を獲得する辺をまとめたもの。
+/// 同じ獲得を異なるラベルの遷移で行うときは、`edges` にそのすべての辺が入る
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockStep {
+    pub held: String,
+    pub acquired: String,
+    pub process: usize,
+    pub edges: Vec<LockEdge>,
+}
+
+/// 辺の閉路のうち、すべての段が異なるプロセスのもの。1つのプロセスだけの閉路ではデッドロックしない。
+/// 同じ閉路は一度だけ、名前が最小のロックから始まる順で返す
+pub fn lock_order_cycles(edges: &[LockEdge]) -> Vec<Vec<LockStep>> {
+    // (held, acquired, process) が同じ辺は1つの段にまとめてから閉路を探す
+    let mut steps: Vec<LockStep> = vec![];
+    for e in edges {
+        match steps
+            .iter_mut()
+            .find(|s| s.held == e.held && s.acquired == e.acquired && s.process == e.process)
+        {
+            Some(step) => step.edges.push(e.clone()),
+            None => steps.push(LockStep {
+                held: e.held.clone(),
+                acquired: e.acquired.clone(),
+                process: e.process,
+                edges: vec![e.clone()],
+            }),
+        }
+    }
+    let mut cycles = vec![];
+    let mut path = vec![];
+    // 閉路の最小のロックから始まる段だけを起点にする
+    for start in steps.iter().filter(|s| s.held < s.acquired) {
+        path.push(start);
+        extend_cycles(&steps, &mut path, &mut cycles);
+        path.pop();
+    }
+    cycles
+}
+
+fn extend_cycles<'a>(
+    steps: &'a [LockStep],
+    path: &mut Vec<&'a LockStep>,
+    cycles: &mut Vec<Vec<LockStep>>,
+) {
+    let first = &path[0].held;
+    let last = path[path.len() - 1];
+    if last.acquired == *first {
+        cycles.push(path.iter().map(|s| (*s).clone()).collect());
+        return;
+    }
+    for s in steps {
+        // 起点より小さいロックは通らない。ロックもプロセスも同じものは2度使わない
+        if s.held != last.acquired
+            || s.acquired < *first
+            || path.iter().any(|p| p.process == s.process)
+            || path.iter().skip(1).any(|p| p.held == s.acquired)
+        {
+            continue;
+        }
+        path.push(s);
+        extend_cycles(steps, path, cycles);
+        path.pop();
+    }
+}
+
+/// 閉路ごとに、各段のすべての辺と辺の遷移元の状態までのトレースを出力する
+pub fn lts_print_lock_cycles<T>(lts: &Lts<T>, cycles: &[Vec<LockStep>]) -> Result<(), Error>
+where
+    T: Debug + Clone,
+{
+    for cycle in cycles {
+        println!("######################################");
+        let mut names = cycle.iter().map(|e| e.held.as_str()).collect::<Vec<_>>();
+        names.push(&cycle[0].held);
+        println!("potential deadlock: {}", names.join(" -> "));
+        let edges = cycle.iter().flat_map(|s| &s.edges).collect::<Vec<_>>();
+        let ids = edges.iter().map(|e| e.state).collect::<Vec<_>>();
+        for (path, edge) in lts.traces_to(&ids).iter().zip(edges) {
+            println!("{}", edge);
+            print_deadlock(path)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddsv::data::{concurrent_composition, Process};
+    use crate::ddsv::program::Program;
+    use crate::ddsv::sync::{Has, Mutex};
+
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+    struct Vars {
+        m0: Mutex,
+        m1: Mutex,
+        done: bool,
+    }
+
+    impl Has<Mutex, 0> for Vars {
+        fn get(&self) -> &Mutex {
+            &self.m0
+        }
+        fn get_mut(&mut self) -> &mut Mutex {
+            &mut self.m0
+        }
+    }
+
+    impl Has<Mutex, 1> for Vars {
+        fn get(&self) -> &Mutex {
+            &self.m1
+        }
+        fn get_mut(&mut self) -> &mut Mutex {
+            &mut self.m1
+        }
+    }
+
+    fn is_done(r: &Vars) -> bool {
+        r.done
+    }
+    fn finish(r: &Vars) -> Vars {
+        Vars {
+            done: true,
+            ..r.clone()
+        }
+    }
+
+    // `A` を持ったまま `B` を獲得して両方を解放する
+    fn nested<const A: usize, const B: usize, const P: usize>(p: &mut Program<Vars>)
+    where
+        Vars: Has<Mutex, A> + Has<Mutex, B>,
+    {
+        let name = |m| format!("m{}", m);
+        p.lock::<A, P>(&name(A))
+            .lock::<B, P>(&name(B))
+            .unlock::<B, P>(&name(B))
+            .unlock::<A, P>(&name(A));
+    }
+
+    fn build<F>(name: &str, f: F) -> Process<Vars>
+    where
+        F: FnOnce(&mut Program<Vars>),
+    {
+        let mut p = Program::new();
+        f(&mut p);
+        p.build(name).unwrap()
+    }
+
+    fn analyze(ps: &[Process<Vars>]) -> (Lts<Vars>, Vec<Vec<LockStep>>) {
+        let lts = concurrent_composition(&Vars::default(), ps).unwrap();
+        let locks = [Lock::mutex::<0>("m0"), Lock::mutex::<1>("m1")];
+        let edges = lock_order_edges(&lts, &locks);
+        let cycles = lock_order_cycles(&edges);
+        (lts, cycles)
+    }
+
+    #[test]
+    fn lock_order_cycles_test() {
+        let p = build("P", nested::<0, 1, 0>);
+        let q = build("Q", nested::<1, 0, 1>);
+        let (lts, cycles) = analyze(&[p.clone(), q]);
+        assert_eq!(lts.deadlocks.len(), 2);
+        assert_eq!(cycles.len(), 1);
+        let found = cycles[0]
+            .iter()
+            .flat_map(|s| &s.edges)
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                "process 0 acquires m1 holding m0 by \"lock m1\"",
+                "process 1 acquires m0 holding m1 by \"lock m0\"",
+            ]
+        );
+        let trace = lts.trace_to(cycles[0][1].edges[0].state).unwrap();
+        assert_eq!((trace.last().unwrap().1).1, ["P1", "Q2"]);
+
+        // Q は P が終わるまで待つのでデッドロックしないが、順序の逆転は見つかる
+        let q = build("Q", |b| {
+            b.await_("done", is_done);
+            nested::<1, 0, 1>(b);
+        });
+        let p_done = build("P", |b| {
+            nested::<0, 1, 0>(b);
+            b.assign("done = true", finish);
+        });
+        let (lts, cycles) = analyze(&[p_done, q]);
+        assert_eq!(lts.deadlocks.len(), 1);
+        assert_eq!(cycles.len(), 1);
+
+        // 同じ順序で獲得するか、1つのプロセスだけが逆順に獲得するなら閉路はない
+        let q = build("Q", nested::<0, 1, 1>);
+        assert!(analyze(&[p.clone(), q]).1.is_empty());
+        let p_both = build("P", |b| {
+            nested::<0, 1, 0>(b);
+            nested::<1, 0, 0>(b);
+        });
+        assert!(analyze(&[p_both]).1.is_empty());
+
+        // 同じ獲得を異なるラベルの遷移で行っても、閉路は1つにまとめて辺をすべて付ける
+        let p_twice = build("P", |b| {
+            nested::<0, 1, 0>(b);
+            b.lock::<0, 0>("again m0")
+                .lock::<1, 0>("again m1")
+                .unlock::<1, 0>("m1")
+                .unlock::<0, 0>("m0");
+        });
+        let q = build("Q", nested::<1, 0, 1>);
+        let (_, cycles) = analyze(&[p_twice, q]);
+        assert_eq!(cycles.len(), 1);
+        let labels = cycles[0]
+            .iter()
+            .map(|s| s.edges.iter().map(|e| e.label.as_str()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, [vec!["lock m1", "lock again m1"], vec!["lock m0"]]);
+    }
+}
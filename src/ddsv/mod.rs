@@ -1,30 +1,51 @@
 pub mod data;
+pub mod error;
+pub mod format;
+pub mod lock_order;
+pub mod model;
+pub mod program;
+pub mod race;
+pub mod random;
+pub mod refinement;
+pub mod replay;
+pub mod serialize;
+pub mod simulator;
+pub mod sweep;
+pub mod sync;
+pub mod validate;
 
 type Guard<T> = fn(&T) -> bool;
 type Action<T> = fn(&T) -> T;
+type Choice<T> = fn(&T) -> Vec<T>;
+type Check<T> = fn(&T) -> Result<T, String>;
+type GlobalGuard<T> = fn(&State<T>) -> bool;
+type GlobalAction<T> = fn(&State<T>) -> T;
 type Label = String;
 type Location = String;
+type LocId = u32;
 type State<T> = (T, Vec<Location>);
+type Packed<T> = (T, Vec<LocId>);
 type Path<T> = Vec<(Label, State<T>)>;
+type Edges = Vec<(Label, i32)>;
+// 状態と、そこへ遷移したときのラベルの列
+type Trace<S, L> = Vec<(L, S)>;
+// 遷移のラベルと遷移先。遷移が失敗したら遷移先の代わりにメッセージ
+type Step<S, L> = (L, Result<S, String>);
+type Successors<T> = Vec<Step<Packed<T>, Label>>;
+// 状態から実行できる遷移を2番目の引数のバッファに書き込む
+type NextFn<T> = Box<dyn Fn(&Packed<T>, &mut Successors<T>) -> Result<(), error::Error>>;
 
 #[cfg(test)]
 mod tests {
     use super::data::*;
-    use env_logger;
     use std::env;
-    #[derive(Clone, Eq, Hash)]
+    #[derive(Clone, PartialEq, Eq, Hash)]
     struct SharedVars {
         x: i32,
         t1: i32,
         t2: i32,
     }
 
-    impl PartialEq for SharedVars {
-        fn eq(&self, other: &Self) -> bool {
-            self.x == other.x && self.t1 == other.t1 && self.t2 == other.t2
-        }
-    }
-
     impl SharedVars {
         fn new() -> SharedVars {
             SharedVars { x: 0, t1: 0, t2: 0 }
@@ -85,9 +106,128 @@ mod tests {
         let t = Trans::new("read", "P1", always_true, return_copied);
         assert_eq!(t.label, String::from("read"));
         assert_eq!(t.location, String::from("P1"));
-        assert_eq!((t.guard)(&SharedVars::new()), true);
-        assert_eq!((t.action)(&SharedVars::new()), SharedVars::new());
+        assert!(t.guard.holds(&SharedVars::new(), || unreachable!()));
+        assert_eq!(
+            t.action.apply(&SharedVars::new(), || unreachable!()),
+            [Ok(SharedVars::new())]
+        );
     }
+    #[test]
+    fn choice_test() {
+        fn read_any(r: &SharedVars) -> Vec<SharedVars> {
+            (0..3).map(|t1| SharedVars { t1, ..r.clone() }).collect()
+        }
+        let process = Process::new(vec![
+            (
+                "P0",
+                vec![Trans::choice("read", "P1", always_true, read_any)],
+            ),
+            ("P1", vec![]),
+        ]);
+        let lts = concurrent_composition(&SharedVars::new(), &[process]).unwrap();
+        let states = lts_states(&lts).collect::<Vec<_>>();
+        assert_eq!(states.len(), 4);
+        let trans = states[0].2;
+        assert_eq!(trans.len(), 3);
+        for (t1, (label, id)) in trans.iter().enumerate() {
+            let (r, locs) = lts.unpack(states[*id as usize].0);
+            assert_eq!(label, "read");
+            assert_eq!(r.t1, t1 as i32);
+            assert_eq!(locs, ["P1"]);
+        }
+        assert_eq!(lts.deadlocks.len(), 3);
+    }
+
+    #[test]
+    fn violation_test() {
+        fn read_any(r: &SharedVars) -> Vec<SharedVars> {
+            (0..3).map(|t1| SharedVars { t1, ..r.clone() }).collect()
+        }
+        fn checked_write(r: &SharedVars) -> Result<SharedVars, String> {
+            if r.t1 > 1 {
+                Err(String::from("t1 overflowed"))
+            } else {
+                Ok(move_t1_to_x(r))
+            }
+        }
+        let process = Process::new(vec![
+            (
+                "P0",
+                vec![Trans::choice("read", "P1", always_true, read_any)],
+            ),
+            (
+                "P1",
+                vec![Trans::check("write", "P2", always_true, checked_write)],
+            ),
+            ("P2", vec![]),
+        ]);
+        let lts = concurrent_composition(&SharedVars::new(), &[process]).unwrap();
+        let violations = &lts.violations;
+        assert_eq!(lts.states.len(), 6);
+        assert_eq!(lts.deadlocks.len(), 2);
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].label, "write");
+        assert_eq!(violations[0].message, "t1 overflowed");
+        let trace = lts.trace_to(violations[0].state).unwrap();
+        assert_eq!(trace.len(), 2);
+        assert_eq!((trace[1].1).0.t1, 2);
+
+        let mut out = vec![];
+        write_lts_dot(&mut out, &lts).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("3 [label=\"3\\nP1 \\nx=0 t1=2 t2=0\",];\n"));
+        assert!(out.contains(
+            "e0 [label=\"t1 overflowed\",shape=box,style=filled,fillcolor=orange];\n\
+             3 -> e0 [label=\"write\"];\n"
+        ));
+    }
+
+    #[test]
+    fn global_test() {
+        fn q_outside(s: &super::State<SharedVars>) -> bool {
+            s.1[1] != "Q1"
+        }
+        fn p_outside(s: &super::State<SharedVars>) -> bool {
+            s.1[0] != "P1"
+        }
+        fn count_processes(s: &super::State<SharedVars>) -> SharedVars {
+            SharedVars {
+                x: s.1.len() as i32,
+                ..s.0.clone()
+            }
+        }
+        let ps = [
+            Process::new(vec![
+                (
+                    "P0",
+                    vec![Trans::global("enter", "P1", q_outside, count_processes)],
+                ),
+                (
+                    "P1",
+                    vec![Trans::new("leave", "P0", always_true, return_copied)],
+                ),
+            ]),
+            Process::new(vec![
+                (
+                    "Q0",
+                    vec![Trans::global("enter", "Q1", p_outside, count_processes)],
+                ),
+                (
+                    "Q1",
+                    vec![Trans::new("leave", "Q0", always_true, return_copied)],
+                ),
+            ]),
+        ];
+        let lts = concurrent_composition(&SharedVars::new(), &ps).unwrap();
+        let states = lts_states(&lts).collect::<Vec<_>>();
+        assert_eq!(states.len(), 4);
+        assert!(states
+            .iter()
+            .all(|((_, locs), _, _)| lts.location_names(locs) != ["P1", "Q1"]));
+        assert!(states.iter().skip(1).all(|((r, _), _, _)| r.x == 2));
+        assert!(lts.deadlocks.is_empty());
+    }
+
     #[test]
     fn trans_print_test() {
         let t = Trans::new("read", "P1", always_true, return_copied);
@@ -130,39 +270,153 @@ mod tests {
             ),
             ("Q3", vec![]),
         ]);
-        let v = make_initial_state(&r0, &vec![process_p, process_q]);
+        let v = make_initial_state(&r0, &[process_p, process_q]).unwrap();
         assert_eq!(v.0, r0.clone());
         assert_eq!(v.1[0], "P0");
         assert_eq!(v.1[1], "Q0");
     }
 
     #[test]
-    fn calc_transitions_test() {
-        let r0 = SharedVars::new();
-        let next = calc_transitions(
-            vec![],
-            &r0,
-            &[String::from("P1")],
-            &[String::from("Q1")],
-            &[Trans::new("write", "Q3", always_true, increment_t1)],
+    fn write_dot_test() {
+        let process = Process::new(vec![
+            (
+                "P0",
+                vec![Trans::new("read", "P1", always_true, move_x_to_t1)],
+            ),
+            ("P1", vec![]),
+        ]);
+        let mut out = vec![];
+        process.write_dot(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "digraph {\n\"P0\";\n\"P1\";\n\"P0\" -> \"P1\" [label=\"read\"];\n}\n"
         );
-        assert_eq!(next.len(), 1);
-        assert_eq!(next[0].0, "write");
-        let mut r1 = r0.clone();
-        r1.t1 = 1;
-        assert_eq!((next[0].1).0, r1);
-        assert_eq!((next[0].1).1, vec!["P1", "Q3", "Q1"]);
     }
 
     #[test]
-    fn collect_trans_test() {
-        init();
-        let calcs = collect_trans(
-            vec![],
-            &SharedVars::new(),
-            &[],
-            &[String::from("P0"), String::from("Q0")],
-            &vec![
+    fn write_lts_dot_quote_test() {
+        fn refuse(_r: &SharedVars) -> Result<SharedVars, String> {
+            Err(String::from("say \"no\""))
+        }
+        let process = Process::new(vec![
+            (
+                "\"P0\"",
+                vec![
+                    Trans::new("\"go\"", "P\\1", always_true, return_copied),
+                    Trans::check("stop", "P\\1", always_true, refuse),
+                ],
+            ),
+            ("P\\1", vec![]),
+        ]);
+        let lts =
+            concurrent_composition(&SharedVars::new(), std::slice::from_ref(&process)).unwrap();
+        let mut out = vec![];
+        write_lts_dot(&mut out, &lts).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "digraph{\n\
+             0 [label=\"0\\n\\\"P0\\\" \\nx=0 t1=0 t2=0\",style=filled,fillcolor=cyan];\n\
+             1 [label=\"1\\nP\\\\1 \\nx=0 t1=0 t2=0\",style=filled,fillcolor=pink];\n\
+             0 -> 1 [label=\"\\\"go\\\"\"];\n\
+             e0 [label=\"say \\\"no\\\"\",shape=box,style=filled,fillcolor=orange];\n\
+             0 -> e0 [label=\"stop\"];\n\
+             }\n"
+        );
+
+        let mut out = vec![];
+        process.write_dot(&mut out).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains("\"\\\"P0\\\"\" -> \"P\\\\1\" [label=\"\\\"go\\\"\"];\n"));
+    }
+
+    #[test]
+    fn write_lts_dot_with_test() {
+        let process = Process::new(vec![
+            (
+                "P0",
+                vec![Trans::new("inc", "P1", always_true, increment_t1)],
+            ),
+            (
+                "P1",
+                vec![Trans::new("write", "P2", always_true, move_t1_to_x)],
+            ),
+            ("P2", vec![]),
+        ]);
+        let lts = concurrent_composition(&SharedVars::new(), &[process]).unwrap();
+        let opts = DotOptions {
+            highlight: lts.trace_to(lts.deadlocks[0]),
+            rankdir: Some(String::from("LR")),
+            diff_edges: true,
+            max_nodes: Some(2),
+            ..DotOptions::default()
+        };
+        let mut out = vec![];
+        write_lts_dot_with(&mut out, &lts, &opts).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "digraph{\n\
+             rankdir=LR;\n\
+             0 [label=\"0\\nP0 \\nx=0 t1=0 t2=0\",style=filled,fillcolor=cyan,color=red,penwidth=3];\n\
+             1 [label=\"1\\nP1 \",color=red,penwidth=3];\n\
+             0 -> 1 [label=\"inc\\nt1=1\",color=red,penwidth=3];\n\
+             // 2 of 3 states shown\n\
+             }\n"
+        );
+
+        let opts = DotOptions {
+            cluster_by: Some(1),
+            ..DotOptions::default()
+        };
+        let err = write_lts_dot_with(&mut vec![], &lts, &opts).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cluster_by 1 is out of range for 1 processes"
+        );
+    }
+
+    #[test]
+    fn diff_edges_derived_test() {
+        #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+        struct Buffer {
+            name: String,
+            items: Vec<i32>,
+            count: i32,
+        }
+        fn push(r: &Buffer) -> Buffer {
+            let mut s = r.clone();
+            s.items.push(s.count);
+            s.count += 1;
+            s
+        }
+        fn always(_r: &Buffer) -> bool {
+            true
+        }
+        let process = Process::new(vec![
+            ("P0", vec![Trans::new("push", "P1", always, push)]),
+            ("P1", vec![]),
+        ]);
+        let r0 = Buffer {
+            name: String::from("a, b"),
+            items: vec![],
+            count: 0,
+        };
+        let lts = concurrent_composition(&r0, &[process]).unwrap();
+        let opts = DotOptions {
+            diff_edges: true,
+            ..DotOptions::default()
+        };
+        let mut out = vec![];
+        write_lts_dot_with(&mut out, &lts, &opts).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        // 名前の中のカンマや空白では区切らず、変化したフィールドだけを表示する
+        assert!(out.contains("0 -> 1 [label=\"push\\nitems: [0] count: 1\"];\n"));
+    }
+
+    #[test]
+    fn stable_output_test() {
+        let processes = || {
+            vec![
                 Process::new(vec![
                     (
                         "P0",
@@ -193,14 +447,436 @@ mod tests {
                     ),
                     ("Q3", vec![]),
                 ]),
-            ],
+            ]
+        };
+        let dot = || {
+            let lts = concurrent_composition(&SharedVars::new(), &processes()).unwrap();
+            let mut out = vec![];
+            write_lts_dot(&mut out, &lts).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        let first = dot();
+        assert_eq!(first, dot());
+        // 状態1はPが先に動いた遷移先
+        assert!(first.contains("1 [label=\"1\\nP1 Q0 \\n"));
+        assert!(first.contains("0 -> 1 [label=\"read\"];\n0 -> 2 [label=\"read\"];\n"));
+    }
+
+    #[test]
+    fn termination_test() {
+        let process = Process::new(vec![
+            (
+                "P0",
+                vec![
+                    Trans::new("read", "P1", always_true, move_x_to_t1),
+                    Trans::new("inc", "P2", always_true, increment_t1),
+                ],
+            ),
+            ("P1", vec![]),
+            ("P2", vec![]),
+        ]);
+        let lts = concurrent_composition(&SharedVars::new(), &[process]).unwrap();
+        let termination = Termination::end_locations(&[&["P2"]]);
+        let (terminated, deadlock) = lts_split_deadlocks(&lts, &termination);
+        assert_eq!(terminated.len(), 1);
+        assert_eq!((terminated[0][1].1).1, ["P2"]);
+        assert_eq!(deadlock.len(), 1);
+        assert_eq!((deadlock[0][1].1).1, ["P1"]);
+
+        let opts = DotOptions {
+            termination: Some(termination),
+            ..DotOptions::default()
+        };
+        let mut out = vec![];
+        write_lts_dot_with(&mut out, &lts, &opts).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("1 [label=\"1\\nP1 \\nx=0 t1=0 t2=0\",style=filled,fillcolor=pink];"));
+        assert!(
+            out.contains("2 [label=\"2\\nP2 \\nx=0 t1=1 t2=0\",style=filled,fillcolor=palegreen];")
         );
+    }
+
+    #[test]
+    fn trace_to_test() {
+        let process = Process::new(vec![
+            (
+                "P0",
+                vec![Trans::new("read", "P1", always_true, move_x_to_t1)],
+            ),
+            (
+                "P1",
+                vec![Trans::new("inc", "P2", always_true, increment_t1)],
+            ),
+            (
+                "P2",
+                vec![Trans::new("write", "P3", always_true, move_t1_to_x)],
+            ),
+            ("P3", vec![]),
+        ]);
+        let lts = concurrent_composition(&SharedVars::new(), &[process]).unwrap();
+        assert_eq!(lts.deadlocks, [3]);
+        let steps = lts.steps_to(3).unwrap();
+        let steps = steps
+            .iter()
+            .map(|(label, id)| (label.as_str(), *id))
+            .collect::<Vec<_>>();
+        assert_eq!(steps, [("---", 0), ("read", 1), ("inc", 2), ("write", 3)]);
+        let trace = lts.trace_to(3).unwrap();
+        assert_eq!(trace.len(), 4);
+        assert_eq!((trace[3].1).0.x, 1);
+        assert_eq!((trace[3].1).1, ["P3"]);
+        assert_eq!(lts.trace_to(0).unwrap().len(), 1);
+        assert!(lts.trace_to(4).is_none());
+        assert!(lts.trace_to(-1).is_none());
+    }
+
+    #[test]
+    fn malformed_model_test() {
+        let process = Process::new(vec![(
+            "P0",
+            vec![Trans::new("read", "P1", always_true, move_x_to_t1)],
+        )]);
+        let err = concurrent_composition(&SharedVars::new(), &[process]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "malformed model: process 0: transition \"read\" from P0 targets undeclared location P1"
+        );
+    }
+
+    #[test]
+    fn unreachable_location_test() {
+        // P9 にはどこからも遷移しないが、警告になるだけで合成はできる
+        let process = Process::new(vec![
+            (
+                "P0",
+                vec![Trans::new("read", "P1", always_true, move_x_to_t1)],
+            ),
+            ("P1", vec![]),
+            (
+                "P9",
+                vec![Trans::new("inc", "P1", always_true, increment_t1)],
+            ),
+        ]);
+        let lts = concurrent_composition(&SharedVars::new(), &[process]).unwrap();
+        assert_eq!(lts.states.len(), 2);
+        assert_eq!(lts.locations[0], ["P0", "P1", "P9"]);
+    }
+
+    #[test]
+    fn limit_exceeded_test() {
+        let process = Process::new(vec![(
+            "P0",
+            vec![Trans::new("inc", "P0", always_true, increment_t1)],
+        )]);
+        let err =
+            concurrent_composition_with_limit(&SharedVars::new(), &[process], 10).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "state space exceeds the limit of 10 states"
+        );
+    }
+
+    #[test]
+    fn pack_error_test() {
+        let net = Network::new(&[Process::new(vec![("P0", vec![])])]).unwrap();
+        let err = net
+            .pack(&(SharedVars::new(), vec![String::from("Q0")]))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "malformed model: process 0 has no location Q0"
+        );
+        let err = net.pack(&(SharedVars::new(), vec![])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "malformed model: 1 processes but the state has 0 locations"
+        );
+    }
+
+    #[test]
+    fn calc_transitions_test() {
+        let r0 = SharedVars::new();
+        let net = Network::new(&[
+            Process::new(vec![("P0", vec![])]),
+            Process::new(vec![
+                (
+                    "Q0",
+                    vec![Trans::new("write", "Q1", always_true, increment_t1)],
+                ),
+                ("Q1", vec![]),
+            ]),
+            Process::new(vec![("R0", vec![])]),
+        ])
+        .unwrap();
+        let s0 = net.initial_state(&r0);
+        assert!(calc_transitions(vec![], &net, &s0, 0).is_empty());
+        let next = calc_transitions(vec![], &net, &s0, 1);
+        assert_eq!(next.len(), 1);
+        assert_eq!(next[0].0, "write");
+        let mut r1 = r0.clone();
+        r1.t1 = 1;
+        let target = next[0].1.as_ref().unwrap();
+        assert_eq!(target, &(r1, vec![0, 1, 0]));
+        assert_eq!(net.unpack(target).1, ["P0", "Q1", "R0"]);
+    }
+
+    #[test]
+    fn collect_trans_test() {
+        init();
+        let net = Network::new(&[
+            Process::new(vec![
+                (
+                    "P0",
+                    vec![Trans::new("read", "P1", always_true, move_x_to_t1)],
+                ),
+                (
+                    "P1",
+                    vec![Trans::new("inc", "P2", always_true, increment_t1)],
+                ),
+                (
+                    "P2",
+                    vec![Trans::new("write", "P3", always_true, move_t1_to_x)],
+                ),
+                ("P3", vec![]),
+            ]),
+            Process::new(vec![
+                (
+                    "Q0",
+                    vec![Trans::new("read", "Q1", always_true, move_x_to_t2)],
+                ),
+                (
+                    "Q1",
+                    vec![Trans::new("inc", "Q2", always_true, increment_t2)],
+                ),
+                (
+                    "Q2",
+                    vec![Trans::new("write", "Q3", always_true, move_t2_to_x)],
+                ),
+                ("Q3", vec![]),
+            ]),
+        ])
+        .unwrap();
+        let calcs = collect_trans(&net, &net.initial_state(&SharedVars::new()));
+        let calcs = calcs
+            .into_iter()
+            .map(|(label, target)| (label, target.map(|s| net.unpack(&s))))
+            .collect::<Vec<_>>();
         assert_eq!(calcs.len(), 2);
         assert_eq!(calcs[0].0, "read");
-        assert_eq!((calcs[0].1).0, SharedVars::new());
-        assert_eq!((calcs[0].1).1, ["P0", "Q1"]);
+        assert_eq!(calcs[0].1.as_ref().unwrap().0, SharedVars::new());
+        assert_eq!(calcs[0].1.as_ref().unwrap().1, ["P1", "Q0"]);
         assert_eq!(calcs[1].0, "read");
-        assert_eq!((calcs[1].1).0, SharedVars::new());
-        assert_eq!((calcs[1].1).1, ["P1", "Q0"]);
+        assert_eq!(calcs[1].1.as_ref().unwrap().0, SharedVars::new());
+        assert_eq!(calcs[1].1.as_ref().unwrap().1, ["P0", "Q1"]);
+    }
+
+    #[test]
+    fn collect_trans_into_test() {
+        let ps = (0..8)
+            .map(|i| {
+                let (l0, l1) = (format!("P{}_0", i), format!("P{}_1", i));
+                Process::new(vec![
+                    (
+                        &l0,
+                        vec![Trans::new("enter", &l1, always_true, return_copied)],
+                    ),
+                    (
+                        &l1,
+                        vec![Trans::new("back", &l0, always_true, return_copied)],
+                    ),
+                ])
+            })
+            .collect::<Vec<_>>();
+        let net = Network::new(&ps).unwrap();
+        let s0 = net.initial_state(&SharedVars::new());
+        let mut buf = vec![(String::from("stale"), Err(String::new()))];
+        collect_trans_into(&mut buf, &net, &s0);
+        assert_eq!(buf, collect_trans(&net, &s0));
+        // プロセスの番号順に並ぶ
+        for (k, (label, target)) in buf.iter().enumerate() {
+            assert_eq!(label, "enter");
+            assert_eq!(target.as_ref().unwrap().1[k], 1);
+        }
+        let lts = concurrent_composition(&SharedVars::new(), &ps).unwrap();
+        assert_eq!(lts.states.len(), 256);
+        assert!(lts.deadlocks.is_empty());
+    }
+
+    #[test]
+    fn composition_model_test() {
+        let process = Process::new(vec![
+            (
+                "P0",
+                vec![
+                    Trans::new("read", "P1", always_true, move_x_to_t1),
+                    Trans::new("inc", "P2", always_true, increment_t1),
+                ],
+            ),
+            ("P1", vec![]),
+            ("P2", vec![]),
+        ]);
+        let ps = [process];
+        let model = Composition::new(&SharedVars::new(), &ps).unwrap();
+        let space = super::model::explore(&model).unwrap();
+        let lts = concurrent_composition(&SharedVars::new(), &ps).unwrap();
+        assert_eq!(space.states.len(), lts.states.len());
+        assert_eq!(space.deadlocks, lts.deadlocks);
+        assert_eq!(space.states, lts.states);
+        for (id, s) in space.states.iter().enumerate() {
+            assert_eq!(lts.id_of(&lts.unpack(s)), Some(id as i32));
+        }
+    }
+
+    #[test]
+    fn model_lts_test() {
+        use super::model::Model;
+        use super::refinement::check_traces_refinement;
+
+        // `max` まで2ずつ進めるか、0に戻す
+        struct Steps {
+            max: u32,
+        }
+
+        impl Model for Steps {
+            type State = u32;
+            type Label = char;
+
+            fn initial_states(&self) -> Vec<u32> {
+                vec![0]
+            }
+
+            fn successors(
+                &self,
+                s: &u32,
+                buf: &mut Vec<super::Step<u32, char>>,
+            ) -> Result<(), super::error::Error> {
+                if s + 2 <= self.max {
+                    buf.push(('+', Ok(s + 2)));
+                }
+                if *s == self.max {
+                    buf.push(('0', Ok(0)));
+                }
+                Ok(())
+            }
+        }
+
+        let lts = model_lts(&Steps { max: 4 }).unwrap();
+        assert_eq!(lts.states.len(), 3);
+        assert!(lts.deadlocks.is_empty());
+        let trace = lts.trace_to(2).unwrap();
+        assert_eq!(trace.last().unwrap(), &(String::from("+"), (4, vec![])));
+
+        // 奇数の上限では戻れずにデッドロックする
+        let stuck = model_lts(&Steps { max: 3 }).unwrap();
+        assert_eq!(stuck.deadlocks, [1]);
+        assert_eq!(check_traces_refinement(&lts, &stuck), Ok(()));
+        assert!(check_traces_refinement(&stuck, &lts).is_err());
+
+        let mut out = vec![];
+        write_lts_dot(&mut out, &stuck).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .contains("0 -> 1 [label=\"+\"];"));
+        assert!(model_lts_with_limit(&Steps { max: 4 }, 2).is_err());
+    }
+
+    #[test]
+    fn initial_states_test() {
+        let process = Process::new(vec![
+            (
+                "P0",
+                vec![Trans::new("inc", "P1", always_true, increment_t1)],
+            ),
+            ("P1", vec![]),
+        ]);
+        let r1 = SharedVars { x: 1, t1: 0, t2: 0 };
+        let r2 = SharedVars { x: 0, t1: 1, t2: 0 };
+        let r0s = [SharedVars::new(), r1, SharedVars::new(), r2];
+        let lts = concurrent_composition_from(&r0s, &[process]).unwrap();
+        // 同じ初期値は1つにまとめる
+        assert_eq!(lts.initials, 3);
+        assert_eq!(lts.states.len(), 6);
+        assert!(lts.is_initial(2) && !lts.is_initial(3));
+        assert_eq!(lts.deadlocks, [3, 4, 5]);
+        let trace = lts.trace_to(5).unwrap();
+        assert_eq!(trace.len(), 2);
+        assert_eq!((trace[0].1).0.t1, 1);
+        assert_eq!((trace[1].1).0.t1, 2);
+        assert_eq!(lts.trace_to(2).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn atomic_sequence_test() {
+        fn is_one(r: &SharedVars) -> bool {
+            r.x == 1
+        }
+        let inc = |p: &str, read: super::Action<SharedVars>, inc, write| {
+            let l = (0..4).map(|i| format!("{}{}", p, i)).collect::<Vec<_>>();
+            Process::new(vec![
+                (&l[0], vec![Trans::new("read", &l[1], always_true, read)]),
+                (&l[1], vec![Trans::new("inc", &l[2], always_true, inc)]),
+                (&l[2], vec![Trans::new("write", &l[3], always_true, write)]),
+                (&l[3], vec![]),
+            ])
+            .atomic(&[&l[0], &l[1], &l[2], &l[3]])
+            .unwrap()
+        };
+        let ps = [
+            inc("P", move_x_to_t1, increment_t1, move_t1_to_x),
+            inc("Q", move_x_to_t2, increment_t2, move_t2_to_x),
+        ];
+        let lts = concurrent_composition(&SharedVars::new(), &ps).unwrap();
+        // 読み書きが割り込まれないので更新は失われない
+        assert_eq!(lts.states.len(), 5);
+        assert_eq!(lts.deadlocks.len(), 2);
+        for id in &lts.deadlocks {
+            let trace = lts.trace_to(*id).unwrap();
+            assert_eq!((trace.last().unwrap().1).0.x, 2);
+        }
+        let steps = lts.steps_to(lts.deadlocks[0]).unwrap();
+        assert_eq!(steps[1].0, "read; inc; write");
+
+        // 途中で遷移できなくなる列は、最初の遷移も実行できない
+        let process = Process::new(vec![
+            (
+                "R0",
+                vec![Trans::new("enter", "R1", always_true, return_copied)],
+            ),
+            ("R1", vec![Trans::new("wait", "R2", is_one, return_copied)]),
+            ("R2", vec![]),
+        ])
+        .atomic(&["R0", "R1", "R2"])
+        .unwrap();
+        let ps = [process];
+        let lts = concurrent_composition(&SharedVars::new(), &ps).unwrap();
+        assert_eq!(lts.states.len(), 1);
+        assert_eq!(lts.deadlocks, [0]);
+        assert!(lts.violations.is_empty());
+        let r1 = SharedVars { x: 1, t1: 0, t2: 0 };
+        let lts = concurrent_composition(&r1, &ps).unwrap();
+        assert_eq!(lts.steps_to(1).unwrap()[1].0, "enter; wait");
+
+        // 他のプロセスがガードを成り立たせれば実行できる
+        fn set_one(r: &SharedVars) -> SharedVars {
+            SharedVars { x: 1, ..r.clone() }
+        }
+        let setter = Process::new(vec![
+            ("Q0", vec![Trans::new("set", "Q1", always_true, set_one)]),
+            ("Q1", vec![]),
+        ]);
+        let both = [ps[0].clone(), setter];
+        let lts = concurrent_composition(&SharedVars::new(), &both).unwrap();
+        assert!(lts.violations.is_empty());
+        assert_eq!(lts.deadlocks.len(), 1);
+        let steps = lts.steps_to(lts.deadlocks[0]).unwrap();
+        let labels = steps.iter().map(|(l, _)| l.as_str()).collect::<Vec<_>>();
+        assert_eq!(labels, ["---", "set", "enter; wait"]);
+
+        let [process] = ps;
+        let err = process.atomic(&["R0", "R3"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "malformed model: atomic sequence has undeclared location R3"
+        );
     }
 }
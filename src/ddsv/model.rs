@@ -0,0 +1,358 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Display};
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+
+use super::data::{write_lts_dot, Lts};
+use super::error::Error;
+use super::Step;
+
+/// 探索できるモデル。プロセスの並行合成のほか、手で書いた遷移関数や生成したモデルもこれを実装すれば
+/// 同じ探索と検査にかけられる
+pub trait Model {
+    type State;
+    type Label: Clone;
+
+    /// 初期状態。重複していてもよい
+    fn initial_states(&self) -> Vec<Self::State>;
+
+    /// 状態 `s` から実行できる遷移を `buf` の末尾に加える。失敗する遷移は遷移先の代わりにメッセージを返す
+    fn successors(
+        &self,
+        s: &Self::State,
+        buf: &mut Vec<Step<Self::State, Self::Label>>,
+    ) -> Result<(), Error>;
+}
+
+/// `Model` を幅優先で探索した結果。状態のIDは発見した順の連番で、初期状態が先頭に並ぶ
+#[derive(Debug)]
+pub struct StateSpace<S, L> {
+    /// IDごとの状態
+    pub states: Vec<S>,
+    /// IDごとの遷移 (ラベル, 遷移先のID)
+    pub edges: Vec<Vec<(L, i32)>>,
+    /// IDごとの (親のID, 親からの遷移のラベル)。初期状態は `None`
+    pub parents: Vec<Option<(i32, L)>>,
+    /// 初期状態の数。IDが `0..initials` の状態が初期状態
+    pub initials: usize,
+    /// 遷移先のない状態のID
+    pub deadlocks: Vec<i32>,
+    /// 遷移の失敗 (遷移元のID, ラベル, メッセージ)
+    pub failures: Vec<(i32, L, String)>,
+    pub(crate) index: StateIndex,
+}
+
+// 状態のハッシュからIDを引く索引。状態そのものは `states` にだけ持ち、
+// ハッシュが同じIDの中から `states` と比べて探す
+#[derive(Debug, Default)]
+pub(crate) struct StateIndex(HashMap<u64, Vec<i32>>);
+
+impl StateIndex {
+    fn hash<S: Hash>(state: &S) -> u64 {
+        let mut h = DefaultHasher::new();
+        state.hash(&mut h);
+        h.finish()
+    }
+
+    pub(crate) fn get<S: Hash + Eq>(&self, states: &[S], state: &S) -> Option<i32> {
+        self.0
+            .get(&Self::hash(state))?
+            .iter()
+            .copied()
+            .find(|id| states[*id as usize] == *state)
+    }
+
+    // `state` は `states[id]` に入れたもの
+    pub(crate) fn insert<S: Hash>(&mut self, state: &S, id: i32) {
+        self.0.entry(Self::hash(state)).or_default().push(id);
+    }
+}
+
+impl<S, L> StateSpace<S, L>
+where
+    S: Hash + Eq,
+{
+    pub fn id_of(&self, state: &S) -> Option<i32> {
+        self.index.get(&self.states, state)
+    }
+
+    /// 初期状態から `id` の状態までの最短の (ラベル, 状態) の列。先頭は初期状態でラベルはない
+    pub fn trace_to(&self, id: i32) -> Option<Vec<(Option<&L>, &S)>> {
+        if id < 0 || id as usize >= self.states.len() {
+            return None;
+        }
+        let mut trace = vec![];
+        let mut id = id as usize;
+        loop {
+            match &self.parents[id] {
+                Some((parent, label)) => {
+                    trace.push((Some(label), &self.states[id]));
+                    id = *parent as usize;
+                }
+                None => {
+                    trace.push((None, &self.states[id]));
+                    break;
+                }
+            }
+        }
+        trace.reverse();
+        Some(trace)
+    }
+
+    /// `invariant` が成り立たない状態のID
+    pub fn invariant_violations<F>(&self, invariant: F) -> Vec<i32>
+    where
+        F: Fn(&S) -> bool,
+    {
+        (0..self.states.len() as i32)
+            .filter(|id| !invariant(&self.states[*id as usize]))
+            .collect()
+    }
+}
+
+/// `model` の到達可能な状態をすべて探索する
+pub fn explore<M>(model: &M) -> Result<StateSpace<M::State, M::Label>, Error>
+where
+    M: Model,
+    M::State: Hash + Eq + Clone,
+{
+    explore_with_limit(model, usize::MAX)
+}
+
+/// 状態数が `max_states` を超えたら探索を打ち切って `Error::LimitExceeded` を返す
+pub fn explore_with_limit<M>(
+    model: &M,
+    max_states: usize,
+) -> Result<StateSpace<M::State, M::Label>, Error>
+where
+    M: Model,
+    M::State: Hash + Eq + Clone,
+{
+    let mut space = StateSpace {
+        states: vec![],
+        edges: vec![],
+        parents: vec![],
+        initials: 0,
+        deadlocks: vec![],
+        failures: vec![],
+        index: StateIndex::default(),
+    };
+    let mut que = VecDeque::new();
+    for s in model.initial_states() {
+        if space.index.get(&space.states, &s).is_some() {
+            continue;
+        }
+        if space.states.len() >= max_states {
+            return Err(Error::LimitExceeded { limit: max_states });
+        }
+        let id = space.states.len() as i32;
+        space.index.insert(&s, id);
+        space.states.push(s);
+        space.edges.push(vec![]);
+        space.parents.push(None);
+        que.push_back(id);
+    }
+    space.initials = space.states.len();
+
+    // 遷移先を受け取るバッファは状態ごとに作らずに使い回す
+    let mut buf = vec![];
+    while let Some(id) = que.pop_front() {
+        buf.clear();
+        model.successors(&space.states[id as usize], &mut buf)?;
+        if buf.is_empty() {
+            space.deadlocks.push(id);
+        }
+
+        let mut edges = Vec::with_capacity(buf.len());
+        for (label, target) in buf.drain(..) {
+            match target {
+                Ok(target) => {
+                    let tid = match space.index.get(&space.states, &target) {
+                        Some(tid) => tid,
+                        None => {
+                            if space.states.len() >= max_states {
+                                return Err(Error::LimitExceeded { limit: max_states });
+                            }
+                            // 経路は持たずに親だけを覚えておく。幅優先なので親を辿れば最短のトレースになる
+                            let tid = space.states.len() as i32;
+                            space.index.insert(&target, tid);
+                            space.states.push(target);
+                            space.edges.push(vec![]);
+                            space.parents.push(Some((id, label.clone())));
+                            que.push_back(tid);
+                            tid
+                        }
+                    };
+                    edges.push((label, tid));
+                }
+                Err(message) => space.failures.push((id, label, message)),
+            }
+        }
+        space.edges[id as usize] = edges;
+    }
+    Ok(space)
+}
+
+/// 探索結果をdot形式で書き出す。状態は `Debug`、ラベルは `Display` で表示する。
+/// 場所のないLTSにして `write_lts_dot` で書くので、色分けもLTSと同じになる。
+/// ほかの設定が必要なら `data::model_lts` でLTSにして `write_lts_dot_with` を使う
+pub fn write_space_dot<S, L>(ch: &mut dyn Write, space: &StateSpace<S, L>) -> Result<(), Error>
+where
+    S: Debug + Hash + Eq,
+    L: Display,
+{
+    let states = space.states.iter().map(|s| (s, vec![])).collect::<Vec<_>>();
+    let mut index = StateIndex::default();
+    for (id, s) in states.iter().enumerate() {
+        index.insert(s, id as i32);
+    }
+    let plain = StateSpace {
+        states,
+        edges: space
+            .edges
+            .iter()
+            .map(|edges| edges.iter().map(|(l, t)| (l.to_string(), *t)).collect())
+            .collect(),
+        parents: space
+            .parents
+            .iter()
+            .map(|p| p.as_ref().map(|(id, l)| (*id, l.to_string())))
+            .collect(),
+        initials: space.initials,
+        deadlocks: space.deadlocks.clone(),
+        failures: space
+            .failures
+            .iter()
+            .map(|(id, l, m)| (*id, l.to_string(), m.clone()))
+            .collect(),
+        index,
+    };
+    write_lts_dot(ch, &Lts::from_space(vec![], plain, "---"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0から `max` まで数えるカウンタ。`max` - 1 からは失敗する `jump` もある
+    struct Counter {
+        starts: Vec<u32>,
+        max: u32,
+    }
+
+    impl Model for Counter {
+        type State = u32;
+        type Label = &'static str;
+
+        fn initial_states(&self) -> Vec<u32> {
+            self.starts.clone()
+        }
+
+        fn successors(
+            &self,
+            s: &u32,
+            buf: &mut Vec<(&'static str, Result<u32, String>)>,
+        ) -> Result<(), Error> {
+            if *s < self.max {
+                buf.push(("inc", Ok(s + 1)));
+            }
+            if *s == self.max - 1 {
+                buf.push(("jump", Err(format!("{} is too large", s + 2))));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn explore_test() {
+        let model = Counter {
+            starts: vec![2, 0, 2],
+            max: 4,
+        };
+        let space = explore(&model).unwrap();
+        assert_eq!(space.states, [2, 0, 3, 1, 4]);
+        assert_eq!(space.initials, 2);
+        assert_eq!(space.deadlocks, [4]);
+        assert_eq!(
+            space.failures,
+            [(2, "jump", String::from("5 is too large"))]
+        );
+        assert_eq!(space.id_of(&1), Some(3));
+
+        let trace = space.trace_to(4).unwrap();
+        assert_eq!(trace, [(None, &2), (Some(&"inc"), &3), (Some(&"inc"), &4)]);
+        assert!(space.trace_to(5).is_none());
+        assert_eq!(space.invariant_violations(|s| s % 2 == 0), [2, 3]);
+
+        let err = explore_with_limit(&model, 4).unwrap_err();
+        assert_eq!(err.to_string(), "state space exceeds the limit of 4 states");
+    }
+
+    #[test]
+    fn write_space_dot_test() {
+        let model = Counter {
+            starts: vec![0],
+            max: 1,
+        };
+        let space = explore(&model).unwrap();
+        let mut out = vec![];
+        write_space_dot(&mut out, &space).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "digraph{\n\
+             0 [label=\"0\\n0\",style=filled,fillcolor=cyan];\n\
+             1 [label=\"1\\n1\",style=filled,fillcolor=pink];\n\
+             0 -> 1 [label=\"inc\"];\n\
+             e0 [label=\"2 is too large\",shape=box,style=filled,fillcolor=orange];\n\
+             0 -> e0 [label=\"jump\"];\n\
+             }\n"
+        );
+
+        // 初期状態はデッドロックでもLTSと同じく初期状態の色で塗る
+        let model = Counter {
+            starts: vec![1],
+            max: 1,
+        };
+        let mut out = vec![];
+        write_space_dot(&mut out, &explore(&model).unwrap()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "digraph{\n0 [label=\"0\\n1\",style=filled,fillcolor=cyan];\n}\n"
+        );
+    }
+
+    #[test]
+    fn write_space_dot_quote_test() {
+        struct Quoted;
+
+        impl Model for Quoted {
+            type State = &'static str;
+            type Label = &'static str;
+
+            fn initial_states(&self) -> Vec<&'static str> {
+                vec!["say \"hi\""]
+            }
+
+            fn successors(
+                &self,
+                _s: &&'static str,
+                buf: &mut Vec<Step<&'static str, &'static str>>,
+            ) -> Result<(), Error> {
+                buf.push(("\"go\"", Err(String::from("\"no\""))));
+                Ok(())
+            }
+        }
+
+        let mut out = vec![];
+        write_space_dot(&mut out, &explore(&Quoted).unwrap()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "digraph{\n\
+             0 [label=\"0\\n\\\"say \\\\\\\"hi\\\\\\\"\\\"\",style=filled,fillcolor=cyan];\n\
+             e0 [label=\"\\\"no\\\"\",shape=box,style=filled,fillcolor=orange];\n\
+             0 -> e0 [label=\"\\\"go\\\"\"];\n\
+             }\n"
+        );
+    }
+}
@@ -0,0 +1,545 @@
+// 代入・条件分岐・繰り返し・ロックなどの文を並べてプロセスを組み立てる。
+// 文ごとに場所を1つ作り、場所の名前は `<名前><行番号>` になる。行番号は `listing` で表示する
+// ソースの行に対応する。最後の文の後には `<名前><行数 + 1>` という終わりの場所を置く
+use super::data::{Condition, Effect, Op, Process, Trans};
+use super::error::Error;
+use super::sync::{mutex, Has, Mutex};
+use super::validate::ModelError;
+use super::{Action, Check, Choice, Guard, Label, Location};
+
+#[derive(Clone)]
+enum Stmt<T> {
+    Step(Label, Guard<T>, Op<T>),
+    If(Label, Guard<T>, Vec<Stmt<T>>, Vec<Stmt<T>>),
+    While(Label, Guard<T>, Vec<Stmt<T>>),
+    Atomic(Label, Vec<Stmt<T>>),
+}
+
+impl<T> Stmt<T> {
+    // この文と中の文が占める行数
+    fn lines(&self) -> usize {
+        match self {
+            Stmt::Step(..) => 1,
+            Stmt::If(_, _, then, otherwise) => 1 + lines(then) + lines(otherwise),
+            Stmt::While(_, _, body) | Stmt::Atomic(_, body) => 1 + lines(body),
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Stmt::Step(label, ..) => label.clone(),
+            Stmt::If(label, ..) => format!("if {}", label),
+            Stmt::While(label, ..) => format!("while {}", label),
+            Stmt::Atomic(label, ..) => format!("atomic {}", label),
+        }
+    }
+}
+
+fn lines<T>(stmts: &[Stmt<T>]) -> usize {
+    stmts.iter().map(|s| s.lines()).sum()
+}
+
+fn always_true<T>(_r: &T) -> bool {
+    true
+}
+
+fn return_copied<T: Clone>(r: &T) -> T {
+    r.clone()
+}
+
+/// 文の列。`build` で `Process` に変換する
+#[derive(Clone)]
+pub struct Program<T> {
+    stmts: Vec<Stmt<T>>,
+}
+
+impl<T> Default for Program<T> {
+    fn default() -> Self {
+        Program { stmts: vec![] }
+    }
+}
+
+impl<T> Program<T>
+where
+    T: Clone,
+{
+    pub fn new() -> Program<T> {
+        Program::default()
+    }
+
+    fn push(&mut self, stmt: Stmt<T>) -> &mut Self {
+        self.stmts.push(stmt);
+        self
+    }
+
+    fn block<F>(f: F) -> Vec<Stmt<T>>
+    where
+        F: FnOnce(&mut Program<T>),
+    {
+        let mut p = Program::new();
+        f(&mut p);
+        p.stmts
+    }
+
+    /// 共有変数を書き換える文
+    pub fn assign(&mut self, label: &str, f: Action<T>) -> &mut Self {
+        self.step(label, always_true, f)
+    }
+
+    /// `guard` が成り立つまで待ってから共有変数を書き換える文
+    pub fn step(&mut self, label: &str, guard: Guard<T>, f: Action<T>) -> &mut Self {
+        self.push(Stmt::Step(label.to_string(), guard, Op::Action(f)))
+    }
+
+    /// 共有変数を非決定的に書き換える文
+    pub fn choose(&mut self, label: &str, f: Choice<T>) -> &mut Self {
+        self.push(Stmt::Step(label.to_string(), always_true, Op::Choice(f)))
+    }
+
+    /// 失敗することのある文。失敗は遷移の失敗になる
+    pub fn check(&mut self, label: &str, f: Check<T>) -> &mut Self {
+        self.push(Stmt::Step(label.to_string(), always_true, Op::Check(f)))
+    }
+
+    /// `cond` が成り立つまで待つ文
+    pub fn await_(&mut self, label: &str, cond: Guard<T>) -> &mut Self {
+        self.step(label, cond, return_copied)
+    }
+
+    /// `M` 番目のミューテックスをプロセス `P` として獲得する文。ラベルは `"lock <name>"`
+    pub fn lock<const M: usize, const P: usize>(&mut self, name: &str) -> &mut Self
+    where
+        T: Has<Mutex, M>,
+    {
+        let label = format!("lock {}", name);
+        self.step(&label, mutex::can_lock::<T, M>, mutex::lock::<T, M, P>)
+    }
+
+    /// `M` 番目のミューテックスをプロセス `P` として解放する文。ラベルは `"unlock <name>"`
+    pub fn unlock<const M: usize, const P: usize>(&mut self, name: &str) -> &mut Self
+    where
+        T: Has<Mutex, M>,
+    {
+        let label = format!("unlock {}", name);
+        self.check(&label, mutex::unlock::<T, M, P>)
+    }
+
+    /// `cond` が成り立てば `then`、成り立たなければ `otherwise` を実行する。
+    /// 分岐の遷移のラベルは `label` と `!(label)`
+    pub fn if_else<F, G>(&mut self, label: &str, cond: Guard<T>, then: F, otherwise: G) -> &mut Self
+    where
+        F: FnOnce(&mut Program<T>),
+        G: FnOnce(&mut Program<T>),
+    {
+        let (then, otherwise) = (Self::block(then), Self::block(otherwise));
+        self.push(Stmt::If(label.to_string(), cond, then, otherwise))
+    }
+
+    pub fn if_<F>(&mut self, label: &str, cond: Guard<T>, then: F) -> &mut Self
+    where
+        F: FnOnce(&mut Program<T>),
+    {
+        self.if_else(label, cond, then, |_| {})
+    }
+
+    /// `cond` が成り立つ間 `body` を繰り返す。ループに入る遷移のラベルは `label`、抜ける遷移は `!(label)`
+    pub fn while_<F>(&mut self, label: &str, cond: Guard<T>, body: F) -> &mut Self
+    where
+        F: FnOnce(&mut Program<T>),
+    {
+        let body = Self::block(body);
+        self.push(Stmt::While(label.to_string(), cond, body))
+    }
+
+    /// `body` を割り込みなしに1つの遷移で実行する。中の文が待つ条件が成り立たない実行は遷移にならない。
+    /// 中では `while_` は使えない
+    pub fn atomic<F>(&mut self, label: &str, body: F) -> &mut Self
+    where
+        F: FnOnce(&mut Program<T>),
+    {
+        let body = Self::block(body);
+        self.push(Stmt::Atomic(label.to_string(), body))
+    }
+
+    /// 行番号をつけたソース。`else` の行には番号がない
+    pub fn listing(&self) -> String {
+        let mut out = String::new();
+        write_listing(&mut out, &self.stmts, 1, 0);
+        out
+    }
+
+    /// 場所とソースの行の対応。順番は `build` が返すプロセスの場所と同じ
+    pub fn source_map(&self, name: &str) -> Vec<(Location, String)> {
+        let mut map = vec![];
+        source_map(&mut map, name, &self.stmts, 1);
+        map.push((location(name, lines(&self.stmts) + 1), String::from("end")));
+        map
+    }
+
+    /// 場所の名前を `name` から作ってプロセスに変換する
+    pub fn build(&self, name: &str) -> Result<Process<T>, Error> {
+        let end = location(name, lines(&self.stmts) + 1);
+        let mut locs = vec![];
+        compile(&mut locs, name, &self.stmts, 1, &end)?;
+        locs.sort_by_key(|(line, _, _)| *line);
+        let mut v = locs
+            .into_iter()
+            .map(|(_, l, trans)| (l, trans))
+            .collect::<Vec<_>>();
+        v.push((end, vec![]));
+        Ok(Process(v))
+    }
+}
+
+fn location(name: &str, line: usize) -> Location {
+    format!("{}{}", name, line)
+}
+
+fn write_listing<T>(out: &mut String, stmts: &[Stmt<T>], mut line: usize, depth: usize) {
+    for s in stmts {
+        out.push_str(&format!(
+            "{:>3}: {}{}\n",
+            line,
+            "    ".repeat(depth),
+            s.text()
+        ));
+        match s {
+            Stmt::Step(..) => {}
+            Stmt::If(_, _, then, otherwise) => {
+                write_listing(out, then, line + 1, depth + 1);
+                if !otherwise.is_empty() {
+                    out.push_str(&format!("     {}else\n", "    ".repeat(depth)));
+                    write_listing(out, otherwise, line + 1 + lines(then), depth + 1);
+                }
+            }
+            Stmt::While(_, _, body) | Stmt::Atomic(_, body) => {
+                write_listing(out, body, line + 1, depth + 1)
+            }
+        }
+        line += s.lines();
+    }
+}
+
+fn source_map<T>(
+    map: &mut Vec<(Location, String)>,
+    name: &str,
+    stmts: &[Stmt<T>],
+    mut line: usize,
+) {
+    for s in stmts {
+        map.push((location(name, line), format!("{}: {}", line, s.text())));
+        match s {
+            Stmt::Step(..) | Stmt::Atomic(..) => {}
+            Stmt::If(_, _, then, otherwise) => {
+                source_map(map, name, then, line + 1);
+                source_map(map, name, otherwise, line + 1 + lines(then));
+            }
+            Stmt::While(_, _, body) => source_map(map, name, body, line + 1),
+        }
+        line += s.lines();
+    }
+}
+
+// `line` 行目から始まる `stmts` を (行番号, 場所, 遷移) に変換して `locs` に加え、最初の場所を返す。
+// 最後の文の次は `next` に進む
+fn compile<T>(
+    locs: &mut Vec<(usize, Location, Vec<Trans<T>>)>,
+    name: &str,
+    stmts: &[Stmt<T>],
+    line: usize,
+    next: &str,
+) -> Result<Location, Error>
+where
+    T: Clone,
+{
+    // 後ろの文から変換すると、各文の次の場所がわかっている
+    let starts = stmts
+        .iter()
+        .scan(line, |l, s| {
+            let start = *l;
+            *l += s.lines();
+            Some(start)
+        })
+        .collect::<Vec<_>>();
+    let mut next = next.to_string();
+    for (s, line) in stmts.iter().zip(starts).rev() {
+        let here = location(name, line);
+        let trans = match s {
+            Stmt::Step(label, guard, op) => {
+                vec![trans(label, &next, Condition::Local(*guard), effect(op))]
+            }
+            Stmt::If(label, cond, then, otherwise) => {
+                let t = compile(locs, name, then, line + 1, &next)?;
+                let e = compile(locs, name, otherwise, line + 1 + lines(then), &next)?;
+                branch(label, *cond, &t, &e)
+            }
+            Stmt::While(label, cond, body) => {
+                let b = compile(locs, name, body, line + 1, &here)?;
+                branch(label, *cond, &b, &next)
+            }
+            Stmt::Atomic(label, body) => {
+                let block = Effect::Block(atomic_ops(body)?);
+                vec![trans(label, &next, Condition::Local(always_true), block)]
+            }
+        };
+        locs.push((line, here.clone(), trans));
+        next = here;
+    }
+    Ok(next)
+}
+
+fn effect<T>(op: &Op<T>) -> Effect<T>
+where
+    T: Clone,
+{
+    match op {
+        Op::Action(f) => Effect::Action(*f),
+        Op::Choice(f) => Effect::Choice(*f),
+        Op::Check(f) => Effect::Check(*f),
+        op => Effect::Block(vec![op.clone()]),
+    }
+}
+
+fn trans<T>(label: &str, target: &str, guard: Condition<T>, action: Effect<T>) -> Trans<T> {
+    Trans {
+        label: label.to_string(),
+        location: target.to_string(),
+        guard,
+        action,
+        atomic: false,
+    }
+}
+
+fn branch<T>(label: &str, cond: Guard<T>, then: &str, otherwise: &str) -> Vec<Trans<T>>
+where
+    T: Clone,
+{
+    vec![
+        trans(
+            label,
+            then,
+            Condition::Local(cond),
+            Effect::Action(return_copied),
+        ),
+        trans(
+            &format!("!({})", label),
+            otherwise,
+            Condition::Not(cond),
+            Effect::Action(return_copied),
+        ),
+    ]
+}
+
+fn atomic_ops<T>(stmts: &[Stmt<T>]) -> Result<Vec<Op<T>>, Error>
+where
+    T: Clone,
+{
+    let mut ops = vec![];
+    for s in stmts {
+        match s {
+            Stmt::Step(_, guard, op) => {
+                ops.push(Op::Assume(*guard));
+                ops.push(op.clone());
+            }
+            Stmt::If(_, cond, then, otherwise) => {
+                ops.push(Op::If(*cond, atomic_ops(then)?, atomic_ops(otherwise)?))
+            }
+            Stmt::While(label, ..) => {
+                return Err(ModelError::LoopInAtomic {
+                    label: label.clone(),
+                }
+                .into())
+            }
+            Stmt::Atomic(_, body) => ops.extend(atomic_ops(body)?),
+        }
+    }
+    Ok(ops)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddsv::data::concurrent_composition;
+
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+    struct Vars {
+        x: i32,
+        t: [i32; 2],
+        m: Mutex,
+    }
+
+    impl Has<Mutex, 0> for Vars {
+        fn get(&self) -> &Mutex {
+            &self.m
+        }
+        fn get_mut(&mut self) -> &mut Mutex {
+            &mut self.m
+        }
+    }
+
+    fn read<const P: usize>(r: &Vars) -> Vars {
+        let mut s = r.clone();
+        s.t[P] = r.x;
+        s
+    }
+    fn write<const P: usize>(r: &Vars) -> Vars {
+        let mut s = r.clone();
+        s.x = r.t[P] + 1;
+        s
+    }
+    fn is_small(r: &Vars) -> bool {
+        r.x < 2
+    }
+    fn is_zero(r: &Vars) -> bool {
+        r.x == 0
+    }
+    fn increment(r: &Vars) -> Vars {
+        let mut s = r.clone();
+        s.x += 1;
+        s
+    }
+    fn reset(r: &Vars) -> Vars {
+        let mut s = r.clone();
+        s.x = 0;
+        s
+    }
+
+    // 共有変数 `x` を読んでから1増やして書く
+    fn inc<const P: usize>(atomic: bool, lock: bool) -> Program<Vars> {
+        let mut p = Program::new();
+        if lock {
+            p.lock::<0, P>("m");
+        }
+        if atomic {
+            p.atomic("inc", |b| {
+                b.assign("t = x", read::<P>).assign("x = t + 1", write::<P>);
+            });
+        } else {
+            p.assign("t = x", read::<P>).assign("x = t + 1", write::<P>);
+        }
+        if lock {
+            p.unlock::<0, P>("m");
+        }
+        p
+    }
+
+    // 2つのプロセスが `inc` をしたあと、終わった状態の `x` の値
+    fn final_values(atomic: bool, lock: bool) -> Vec<i32> {
+        let ps = [
+            inc::<0>(atomic, lock).build("P").unwrap(),
+            inc::<1>(atomic, lock).build("Q").unwrap(),
+        ];
+        let lts = concurrent_composition(&Vars::default(), &ps).unwrap();
+        let mut xs = lts
+            .deadlocks
+            .iter()
+            .map(|id| lts.states[*id as usize].0.x)
+            .collect::<Vec<_>>();
+        xs.sort_unstable();
+        xs.dedup();
+        xs
+    }
+
+    #[test]
+    fn build_test() {
+        let mut p = Program::<Vars>::new();
+        p.lock::<0, 0>("m")
+            .if_else(
+                "x == 0",
+                is_zero,
+                |b| {
+                    b.while_("x < 2", is_small, |b| {
+                        b.assign("x += 1", increment);
+                    });
+                },
+                |b| {
+                    b.assign("x = 0", reset);
+                },
+            )
+            .unlock::<0, 0>("m");
+        assert_eq!(
+            p.listing(),
+            "  1: lock m\n  \
+               2: if x == 0\n  \
+               3:     while x < 2\n  \
+               4:         x += 1\n     \
+                  else\n  \
+               5:     x = 0\n  \
+               6: unlock m\n"
+        );
+
+        let process = p.build("P").unwrap();
+        let targets = process
+            .0
+            .iter()
+            .map(|(l, trans)| {
+                let ts = trans
+                    .iter()
+                    .map(|t| format!("{}->{}", t.label, t.location))
+                    .collect::<Vec<_>>();
+                format!("{}: {}", l, ts.join(", "))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            targets,
+            [
+                "P1: lock m->P2",
+                "P2: x == 0->P3, !(x == 0)->P5",
+                "P3: x < 2->P4, !(x < 2)->P6",
+                "P4: x += 1->P3",
+                "P5: x = 0->P6",
+                "P6: unlock m->P7",
+                "P7: ",
+            ]
+        );
+        let locations = process.0.iter().map(|(l, _)| l.clone()).collect::<Vec<_>>();
+        let map = p.source_map("P");
+        assert_eq!(
+            map.iter().map(|(l, _)| l.clone()).collect::<Vec<_>>(),
+            locations
+        );
+        assert_eq!(map[2].1, "3: while x < 2");
+        assert_eq!(map[6].1, "end");
+
+        let lts = concurrent_composition(&Vars::default(), &[process]).unwrap();
+        assert_eq!(lts.deadlocks.len(), 1);
+        let path = lts.trace_to(lts.deadlocks[0]).unwrap();
+        let labels = path
+            .iter()
+            .skip(1)
+            .map(|(l, _)| l.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            labels,
+            ["lock m", "x == 0", "x < 2", "x += 1", "x < 2", "x += 1", "!(x < 2)", "unlock m"]
+        );
+        assert_eq!(path.last().unwrap().1 .0.x, 2);
+    }
+
+    #[test]
+    fn atomic_test() {
+        // 割り込まれると更新が失われる
+        assert_eq!(final_values(false, false), [1, 2]);
+        assert_eq!(final_values(true, false), [2]);
+        assert_eq!(final_values(false, true), [2]);
+
+        let mut p = Program::<Vars>::new();
+        p.atomic("wait", |b| {
+            b.await_("x == 0", is_zero).assign("x += 1", increment);
+        });
+        let ps = [p.build("P").unwrap(), p.build("Q").unwrap()];
+        let lts = concurrent_composition(&Vars::default(), &ps).unwrap();
+        // 2つ目のプロセスは `x == 0` を待ったまま止まる
+        assert_eq!(lts.states.len(), 3);
+        assert_eq!(lts.deadlocks.len(), 2);
+
+        let mut p = Program::<Vars>::new();
+        p.atomic("loop", |b| {
+            b.while_("x < 2", is_small, |_| {});
+        });
+        assert_eq!(
+            p.build("P").unwrap_err().to_string(),
+            "malformed model: while \"x < 2\" cannot be used inside an atomic block"
+        );
+    }
+}
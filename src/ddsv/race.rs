@@ -0,0 +1,524 @@
+// データ競合の検出。到達可能な状態で、2つのプロセスが同じ共有変数に読み書きする遷移を
+// 同時に実行でき、その2つが共通のロックで守られていなければ競合とする。遷移を守るロックは、
+// その遷移を実行できるすべての状態でプロセスが持っているロック (ロックセット) とする。
+// 遷移が読み書きする変数は宣言するか、共有変数の値をずらしたときのガードとアクションの結果を
+// 比べて推定する。プロセスと場所を使うので、`Model` ではなくプロセスの並行合成だけを扱う
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::fmt::Debug;
+
+use super::data::{lts_states, print_deadlock, Lts, Network, Process, Trans};
+use super::error::Error;
+use super::sync::{Has, Mutex};
+use super::validate::ModelError;
+use super::{Label, State};
+
+/// 検査する共有変数。`set` は推定のために値をずらした共有変数を作る
+#[derive(Clone)]
+pub struct Var<T> {
+    pub name: String,
+    pub get: fn(&T) -> i64,
+    pub set: fn(&T, i64) -> T,
+}
+
+impl<T> Var<T> {
+    pub fn new(name: &str, get: fn(&T) -> i64, set: fn(&T, i64) -> T) -> Var<T> {
+        Var {
+            name: String::from(name),
+            get,
+            set,
+        }
+    }
+}
+
+/// ロック。`held(r, k)` はプロセス `k` がロックを持っているか
+#[derive(Clone)]
+pub struct Lock<T> {
+    pub name: String,
+    pub held: fn(&T, usize) -> bool,
+}
+
+impl<T> Lock<T> {
+    pub fn new(name: &str, held: fn(&T, usize) -> bool) -> Lock<T> {
+        Lock {
+            name: String::from(name),
+            held,
+        }
+    }
+
+    /// `sync` の `M` 番目のミューテックス
+    pub fn mutex<const M: usize>(name: &str) -> Lock<T>
+    where
+        T: Has<Mutex, M>,
+    {
+        Lock::new(name, mutex_held::<T, M>)
+    }
+}
+
+fn mutex_held<T, const M: usize>(r: &T, k: usize) -> bool
+where
+    T: Has<Mutex, M>,
+{
+    <T as Has<Mutex, M>>::get(r).owner == Some(k)
+}
+
+/// 遷移が読み書きする変数の名前
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Access {
+    pub reads: BTreeSet<String>,
+    pub writes: BTreeSet<String>,
+}
+
+/// 競合する遷移の片方
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RaceSide {
+    pub process: usize,
+    pub label: Label,
+    /// 変数に書く遷移か
+    pub write: bool,
+}
+
+/// 状態 `state` で同時に実行できる、変数 `var` について競合する2つの遷移
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Race {
+    pub var: String,
+    pub state: i32,
+    pub sides: [RaceSide; 2],
+}
+
+impl fmt::Display for Race {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        let side = |s: &RaceSide| {
+            let op = if s.write { "writes" } else { "reads" };
+            format!("process {} {} by \"{}\"", s.process, op, s.label)
+        };
+        write!(
+            f,
+            "race on {}: {}, {}",
+            self.var,
+            side(&self.sides[0]),
+            side(&self.sides[1])
+        )
+    }
+}
+
+/// 競合の検査の設定。遷移の読み書きは `declare` で宣言したもの以外は推定する
+pub struct RaceCheck<T> {
+    vars: Vec<Var<T>>,
+    locks: Vec<Lock<T>>,
+    declared: Vec<(usize, Label, Access)>,
+}
+
+impl<T> RaceCheck<T>
+where
+    T: Debug + Clone + Eq,
+{
+    pub fn new(vars: Vec<Var<T>>) -> RaceCheck<T> {
+        RaceCheck {
+            vars,
+            locks: vec![],
+            declared: vec![],
+        }
+    }
+
+    /// 読み書きを守るロックを加える
+    pub fn lock(mut self, lock: Lock<T>) -> RaceCheck<T> {
+        self.locks.push(lock);
+        self
+    }
+
+    /// プロセス `process` のラベル `label` の遷移が読み書きする変数を宣言する
+    pub fn declare(
+        mut self,
+        process: usize,
+        label: &str,
+        reads: &[&str],
+        writes: &[&str],
+    ) -> RaceCheck<T> {
+        let names = |vs: &[&str]| vs.iter().map(|v| v.to_string()).collect();
+        let access = Access {
+            reads: names(reads),
+            writes: names(writes),
+        };
+        self.declared.push((process, String::from(label), access));
+        self
+    }
+
+    /// 状態 `state` でプロセス `process` の遷移 `trans` が読み書きする変数
+    pub fn access(&self, process: usize, trans: &Trans<T>, state: &State<T>) -> Access {
+        match self
+            .declared
+            .iter()
+            .find(|(k, label, _)| *k == process && *label == trans.label)
+        {
+            Some((_, _, access)) => access.clone(),
+            None => self.infer(trans, state),
+        }
+    }
+
+    // 変数ごとに値を1つ上下にずらし、遷移の結果が変わるかを見る。
+    // ずらした値がそのまま出力に残らなければ書いている。ずらしたことでガードや他の値、
+    // 書いた値が変われば読んでいる
+    fn infer(&self, trans: &Trans<T>, state: &State<T>) -> Access {
+        let (r, locs) = state;
+        let outputs = |r: &T| trans.action.apply(r, || (r.clone(), locs.clone()));
+        let o1 = outputs(r);
+        let mut access = Access::default();
+        for v in &self.vars {
+            let (mut read, mut write) = (false, false);
+            for delta in [1, -1].iter() {
+                let r2 = (v.set)(r, (v.get)(r) + delta);
+                if !trans.guard.holds(&r2, || (r2.clone(), locs.clone())) {
+                    read = true;
+                    continue;
+                }
+                let o2 = outputs(&r2);
+                let written = |o: &T, r: &T| (v.get)(o) != (v.get)(r);
+                let w = o1.iter().flatten().any(|o| written(o, r))
+                    || o2.iter().flatten().any(|o| written(o, &r2));
+                write = write || w;
+                read = read
+                    || o1.len() != o2.len()
+                    || o1.iter().zip(o2.iter()).any(|pair| match pair {
+                        (Ok(a), Ok(b)) => {
+                            (v.set)(b, (v.get)(a)) != *a || (w && (v.get)(a) != (v.get)(b))
+                        }
+                        (Err(_), Err(_)) => false,
+                        _ => true,
+                    });
+            }
+            if read {
+                access.reads.insert(v.name.clone());
+            }
+            if write {
+                access.writes.insert(v.name.clone());
+            }
+        }
+        access
+    }
+
+    /// `lts` のすべての状態の遷移から競合を探す。`lts` は `ps` を合成したものでなければならない。
+    /// 競合は状態の順に並ぶので、状態までのトレースは最短になる
+    pub fn check(&self, lts: &Lts<T>, ps: &[Process<T>]) -> Result<Vec<Race>, Error> {
+        let net = Network::new(ps)?;
+        if lts.locations.len() != ps.len() {
+            return Err(ModelError::LocationCountMismatch {
+                processes: ps.len(),
+                locations: lts.locations.len(),
+            }
+            .into());
+        }
+        if let Some(k) = (0..ps.len()).find(|k| lts.locations[*k] != net.location_names()[*k]) {
+            return Err(ModelError::LtsMismatch { process: k }.into());
+        }
+
+        // プロセスとラベルごとの遷移の読み書きと、遷移を守るロック
+        let mut sites: Vec<Site> = vec![];
+        let mut index = HashMap::new();
+        // 同時に実行できる遷移の組と、その最初の状態
+        let mut pairs = vec![];
+        let mut paired = HashSet::new();
+        for (s, id, _) in lts_states(lts) {
+            let state = lts.unpack(s);
+            let (r, locs) = s;
+            let mut enabled = vec![];
+            for (k, p) in net.processes().iter().enumerate() {
+                let held = (0..self.locks.len())
+                    .filter(|l| (self.locks[*l].held)(r, k))
+                    .collect::<BTreeSet<_>>();
+                let mut here = vec![];
+                let l = locs[k] as usize;
+                for (i, t) in p.0[l].1.iter().enumerate() {
+                    if !t.guard.holds(r, || state.clone()) {
+                        continue;
+                    }
+                    let access = self.access(k, t, &state);
+                    // 同じラベルでも場所や遷移が違えば別の遷移として扱う
+                    let n = *index.entry((k, l, i)).or_insert_with(|| {
+                        sites.push(Site {
+                            process: k,
+                            label: t.label.clone(),
+                            access: Access::default(),
+                            locks: held.clone(),
+                        });
+                        sites.len() - 1
+                    });
+                    let site = &mut sites[n];
+                    site.access.reads.extend(access.reads);
+                    site.access.writes.extend(access.writes);
+                    site.locks = site.locks.intersection(&held).cloned().collect();
+                    here.push(n);
+                }
+                enabled.push(here);
+            }
+            for (i, ni) in enabled.iter().enumerate() {
+                for nj in enabled.iter().skip(i + 1) {
+                    for pair in ni.iter().flat_map(|a| nj.iter().map(move |b| (*a, *b))) {
+                        if paired.insert(pair) {
+                            pairs.push((pair, id));
+                        }
+                    }
+                }
+            }
+        }
+        let mut races = vec![];
+        for ((a, b), id) in pairs {
+            let (sa, sb) = (&sites[a], &sites[b]);
+            if !sa.locks.is_disjoint(&sb.locks) {
+                continue;
+            }
+            for v in conflicts(&sa.access, &sb.access) {
+                let side = |s: &Site| RaceSide {
+                    process: s.process,
+                    label: s.label.clone(),
+                    write: s.access.writes.contains(&v),
+                };
+                races.push(Race {
+                    state: id,
+                    sides: [side(sa), side(sb)],
+                    var: v,
+                });
+            }
+        }
+        Ok(races)
+    }
+}
+
+// プロセスの1つの遷移。`locks` は遷移を実行できるすべての状態でプロセスが持っているロックの番号
+struct Site {
+    process: usize,
+    label: Label,
+    access: Access,
+    locks: BTreeSet<usize>,
+}
+
+// 片方が書き、もう片方が読むか書く変数
+fn conflicts(a: &Access, b: &Access) -> Vec<String> {
+    let touched = |x: &Access| x.reads.union(&x.writes).cloned().collect::<BTreeSet<_>>();
+    let (ta, tb) = (touched(a), touched(b));
+    a.writes
+        .intersection(&tb)
+        .chain(b.writes.intersection(&ta))
+        .cloned()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+/// 競合を、競合する状態までのトレースと一緒に出力する
+pub fn lts_print_races<T>(lts: &Lts<T>, races: &[Race]) -> Result<(), Error>
+where
+    T: Debug + Clone,
+{
+    let ids = races.iter().map(|r| r.state).collect::<Vec<_>>();
+    for (path, race) in lts.traces_to(&ids).iter().zip(races.iter()) {
+        println!("??????????????????????????????????????");
+        println!("{}", race);
+        print_deadlock(path)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddsv::data::concurrent_composition;
+    use crate::ddsv::program::Program;
+
+    #[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+    struct Vars {
+        x: i32,
+        t: [i32; 2],
+        m: Mutex,
+        done: bool,
+    }
+
+    impl Has<Mutex, 0> for Vars {
+        fn get(&self) -> &Mutex {
+            &self.m
+        }
+        fn get_mut(&mut self) -> &mut Mutex {
+            &mut self.m
+        }
+    }
+
+    fn get_x(r: &Vars) -> i64 {
+        r.x as i64
+    }
+    fn set_x(r: &Vars, x: i64) -> Vars {
+        let mut s = r.clone();
+        s.x = x as i32;
+        s
+    }
+    fn read<const P: usize>(r: &Vars) -> Vars {
+        let mut s = r.clone();
+        s.t[P] = r.x;
+        s
+    }
+    fn write<const P: usize>(r: &Vars) -> Vars {
+        let mut s = r.clone();
+        s.x = r.t[P] + 1;
+        s
+    }
+    fn is_zero(r: &Vars) -> bool {
+        r.x == 0
+    }
+    fn increment(r: &Vars) -> Vars {
+        let mut s = r.clone();
+        s.x += 1;
+        s
+    }
+    fn reset(r: &Vars) -> Vars {
+        let mut s = r.clone();
+        s.x = 0;
+        s
+    }
+
+    fn inc<const P: usize>(lock: bool) -> Process<Vars> {
+        let mut p = Program::new();
+        if lock {
+            p.lock::<0, P>("m");
+        }
+        p.assign("read", read::<P>).assign("write", write::<P>);
+        if lock {
+            p.unlock::<0, P>("m");
+        }
+        p.build(if P == 0 { "P" } else { "Q" }).unwrap()
+    }
+
+    fn check(lock: bool) -> (Lts<Vars>, Vec<Race>) {
+        check_processes(&[inc::<0>(lock), inc::<1>(lock)])
+    }
+
+    fn check_processes(ps: &[Process<Vars>]) -> (Lts<Vars>, Vec<Race>) {
+        let lts = concurrent_composition(&Vars::default(), ps).unwrap();
+        let races = RaceCheck::new(vec![Var::new("x", get_x, set_x)])
+            .lock(Lock::mutex::<0>("m"))
+            .check(&lts, ps)
+            .unwrap();
+        (lts, races)
+    }
+
+    #[test]
+    fn access_test() {
+        let check = RaceCheck::new(vec![Var::new("x", get_x, set_x)]);
+        let access = |t: &Trans<Vars>, x| {
+            let r = Vars {
+                x,
+                ..Vars::default()
+            };
+            let a = check.access(0, t, &(r, vec![String::from("P0")]));
+            (a.reads.len(), a.writes.len())
+        };
+        let read = Trans::new("t = x", "P1", |_| true, read::<0>);
+        let write = Trans::new("x = t + 1", "P1", |_| true, write::<0>);
+        let inc = Trans::new("x += 1", "P1", |_| true, increment);
+        let reset = Trans::new("x = 0", "P1", |_| true, reset);
+        let wait = Trans::new("x == 0", "P1", is_zero, |r| r.clone());
+        assert_eq!(access(&read, 0), (1, 0));
+        // 同じ値を書いても書いたことになる
+        assert_eq!(access(&write, 1), (0, 1));
+        assert_eq!(access(&inc, 0), (1, 1));
+        assert_eq!(access(&reset, 0), (0, 1));
+        assert_eq!(access(&wait, 0), (1, 0));
+
+        let check = check.declare(0, "x = 0", &["x"], &[]);
+        let a = check.access(0, &reset, &(Vars::default(), vec![String::from("P0")]));
+        assert_eq!(a.reads.iter().collect::<Vec<_>>(), ["x"]);
+        assert!(a.writes.is_empty());
+        let a = check.access(1, &reset, &(Vars::default(), vec![String::from("P0")]));
+        assert_eq!(a.writes.iter().collect::<Vec<_>>(), ["x"]);
+    }
+
+    #[test]
+    fn check_test() {
+        let (lts, races) = check(false);
+        let found = races.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                "race on x: process 0 writes by \"write\", process 1 reads by \"read\"",
+                "race on x: process 0 reads by \"read\", process 1 writes by \"write\"",
+                "race on x: process 0 writes by \"write\", process 1 writes by \"write\"",
+            ]
+        );
+        // 最初に競合が起きる状態までの最短のトレース
+        let trace = lts.trace_to(races[0].state).unwrap();
+        let labels = trace.iter().map(|(l, _)| l.as_str()).collect::<Vec<_>>();
+        assert_eq!(labels, ["---", "read"]);
+        assert_eq!((trace[1].1).1, ["P2", "Q1"]);
+
+        let (_, races) = check(true);
+        assert!(races.is_empty());
+    }
+
+    #[test]
+    fn lockset_test() {
+        // 片方だけがロックで守っていれば、同時に実行できるときに競合する
+        let (lts, races) = check_processes(&[inc::<0>(true), inc::<1>(false)]);
+        let found = races.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            found,
+            [
+                "race on x: process 0 writes by \"write\", process 1 reads by \"read\"",
+                "race on x: process 0 reads by \"read\", process 1 writes by \"write\"",
+                "race on x: process 0 writes by \"write\", process 1 writes by \"write\"",
+            ]
+        );
+        assert!(races.iter().all(|r| lts.trace_to(r.state).is_some()));
+
+        // LTSと合成していないプロセスは検査できない
+        let ps = [inc::<0>(true), inc::<1>(true)];
+        let check = RaceCheck::new(vec![Var::new("x", get_x, set_x)]);
+        let err = check.check(&lts, &ps).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "malformed model: process 1 does not match the locations of the LTS"
+        );
+        let err = check.check(&lts, &ps[..1]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "malformed model: 1 processes but the state has 2 locations"
+        );
+    }
+
+    #[test]
+    fn handoff_test() {
+        fn set_5(r: &Vars) -> Vars {
+            Vars { x: 5, ..r.clone() }
+        }
+        fn finish(r: &Vars) -> Vars {
+            Vars {
+                done: true,
+                ..r.clone()
+            }
+        }
+        fn is_done(r: &Vars) -> bool {
+            r.done
+        }
+        // Q は P が書き終わるのを待ってから読むので、書き込みと読み込みは同時に実行できない
+        let mut p = Program::new();
+        p.assign("x = 5", set_5).assign("done = true", finish);
+        let mut q = Program::new();
+        q.await_("done", is_done).assign("use x", read::<1>);
+        let ps = [p.build("P").unwrap(), q.build("Q").unwrap()];
+        let (_, races) = check_processes(&ps);
+        assert!(races.is_empty());
+    }
+
+    #[test]
+    fn same_label_test() {
+        fn keep(r: &Vars) -> Vars {
+            r.clone()
+        }
+        // P の2つの "op" は別の遷移で、x を書く方だけがロックで守られている
+        let mut p = Program::new();
+        p.lock::<0, 0>("m")
+            .assign("op", increment)
+            .unlock::<0, 0>("m")
+            .assign("op", keep);
+        let ps = [p.build("P").unwrap(), inc::<1>(true)];
+        let (_, races) = check_processes(&ps);
+        assert!(races.is_empty());
+    }
+}
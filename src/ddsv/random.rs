@@ -0,0 +1,418 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use super::data::{Composition, Process};
+use super::error::Error;
+use super::model::Model;
+use super::{Label, State, Step, Trace};
+
+/// シード付きの擬似乱数生成器 (SplitMix64)
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// `0..n` から一様に選ぶ。`n` の倍数に収まらない端の値は捨てて引き直す。
+    /// `n` は1以上でなければならず、0ならパニックする
+    pub fn below(&mut self, n: u64) -> u64 {
+        assert!(n > 0, "Rng::below requires n > 0");
+        // 2^64 を `n` で割った余り。これ未満の値を捨てると残りの個数が `n` の倍数になる
+        let threshold = n.wrapping_neg() % n;
+        loop {
+            let x = self.next_u64();
+            if x >= threshold {
+                return x % n;
+            }
+        }
+    }
+}
+
+/// ランダムウォークの設定。`S` は状態、`L` はラベルの型
+pub struct WalkConfig<S, L = Label> {
+    pub seed: u64,
+    /// ウォークの回数
+    pub walks: usize,
+    /// 1回のウォークの最大遷移数
+    pub max_depth: usize,
+    /// 遷移を選ぶ重み。`None` なら一様に選ぶ。失敗する遷移には遷移元の状態を渡す
+    pub weight: Option<fn(&L, &S) -> u32>,
+    /// 到達した状態で成り立つべき条件。成り立たなければ違反として記録する
+    pub assertion: Option<fn(&S) -> bool>,
+}
+
+impl<S, L> Default for WalkConfig<S, L> {
+    fn default() -> Self {
+        WalkConfig {
+            seed: 0,
+            walks: 100,
+            max_depth: 1000,
+            weight: None,
+            assertion: None,
+        }
+    }
+}
+
+/// ランダムウォークの結果。デッドロックと違反は状態ごとに、遷移の失敗は遷移ごとに
+/// 最初に見つかったトレースを残す。トレースの先頭は初期状態で、ラベルは `root`
+#[derive(Debug)]
+pub struct WalkReport<S, L = Label> {
+    pub deadlocks: Vec<Trace<S, L>>,
+    pub violations: Vec<Trace<S, L>>,
+    /// 遷移の失敗。(失敗した遷移の遷移元までのトレース, ラベル, メッセージ)
+    pub errors: Vec<(Trace<S, L>, L, String)>,
+    pub final_states: HashMap<S, usize>,
+}
+
+impl<S, L> WalkReport<S, L>
+where
+    S: Debug,
+{
+    /// 最終状態の出現回数を多い順に並べる
+    pub fn histogram(&self) -> Vec<(&S, usize)> {
+        let mut v = self
+            .final_states
+            .iter()
+            .map(|(s, n)| (s, *n))
+            .collect::<Vec<_>>();
+        v.sort_by_cached_key(|(s, n)| (std::cmp::Reverse(*n), format!("{:?}", s)));
+        v
+    }
+}
+
+fn record<S, L>(paths: &mut Vec<Trace<S, L>>, path: &[(L, S)])
+where
+    S: PartialEq + Clone,
+    L: Clone,
+{
+    let last = &path.last().unwrap().1;
+    if !paths.iter().any(|p| &p.last().unwrap().1 == last) {
+        paths.push(path.to_vec());
+    }
+}
+
+fn choose<S, L>(
+    rng: &mut Rng,
+    source: &S,
+    trans: &[Step<S, L>],
+    config: &WalkConfig<S, L>,
+) -> usize {
+    if let Some(weight) = config.weight {
+        let weights = trans
+            .iter()
+            .map(|(label, target)| weight(label, target.as_ref().unwrap_or(source)) as u64)
+            .collect::<Vec<_>>();
+        let total: u64 = weights.iter().sum();
+        if total > 0 {
+            let mut x = rng.below(total);
+            for (i, w) in weights.iter().enumerate() {
+                if x < *w {
+                    return i;
+                }
+                x -= w;
+            }
+        }
+    }
+    rng.below(trans.len() as u64) as usize
+}
+
+/// `model` の初期状態から `config.walks` 回のランダムウォークを行う。初期状態が複数あれば
+/// ウォークごとにその中から一様に選ぶ。同じシードからは同じ結果が得られる
+pub fn random_walks_model<M>(
+    model: &M,
+    config: &WalkConfig<M::State, M::Label>,
+    root: M::Label,
+) -> Result<WalkReport<M::State, M::Label>, Error>
+where
+    M: Model,
+    M::State: Hash + Eq + Clone,
+    M::Label: PartialEq,
+{
+    let initials = model.initial_states();
+    let mut rng = Rng::new(config.seed);
+    let mut report = WalkReport {
+        deadlocks: vec![],
+        violations: vec![],
+        errors: vec![],
+        final_states: HashMap::new(),
+    };
+    if initials.is_empty() {
+        return Ok(report);
+    }
+
+    let mut trans = vec![];
+    for _ in 0..config.walks {
+        let s0 = initials[rng.below(initials.len() as u64) as usize].clone();
+        let mut violated = config.assertion.is_some_and(|a| !a(&s0));
+        let mut path = vec![(root.clone(), s0)];
+        while !violated && path.len() <= config.max_depth {
+            let source = &path.last().unwrap().1;
+            trans.clear();
+            model.successors(source, &mut trans)?;
+            if trans.is_empty() {
+                record(&mut report.deadlocks, &path);
+                break;
+            }
+            let i = choose(&mut rng, source, &trans, config);
+            match trans.swap_remove(i) {
+                (label, Ok(target)) => path.push((label, target)),
+                (label, Err(message)) => {
+                    let last = &path.last().unwrap().1;
+                    if !report
+                        .errors
+                        .iter()
+                        .any(|(p, l, _)| *l == label && &p.last().unwrap().1 == last)
+                    {
+                        report.errors.push((path.clone(), label, message));
+                    }
+                    break;
+                }
+            }
+            violated = config
+                .assertion
+                .is_some_and(|a| !a(&path.last().unwrap().1));
+        }
+        if violated {
+            record(&mut report.violations, &path);
+        }
+        *report
+            .final_states
+            .entry(path.last().unwrap().1.clone())
+            .or_insert(0) += 1;
+    }
+    Ok(report)
+}
+
+// 場所を名前で表した状態で遷移するプロセスの並行合成。重みと条件に名前の状態を渡すために使う
+struct Named<T>(Composition<T>);
+
+impl<T> Model for Named<T>
+where
+    T: Clone,
+{
+    type State = State<T>;
+    type Label = Label;
+
+    fn initial_states(&self) -> Vec<State<T>> {
+        let net = self.0.network();
+        self.0
+            .initial_states()
+            .iter()
+            .map(|s| net.unpack(s))
+            .collect()
+    }
+
+    fn successors(&self, s: &State<T>, buf: &mut Vec<Step<State<T>, Label>>) -> Result<(), Error> {
+        let net = self.0.network();
+        let mut packed = vec![];
+        self.0.successors(&net.pack(s)?, &mut packed)?;
+        buf.extend(
+            packed
+                .into_iter()
+                .map(|(label, target)| (label, target.map(|t| net.unpack(&t)))),
+        );
+        Ok(())
+    }
+}
+
+/// 共有変数が `r0` の初期状態からプロセスの並行合成の上をランダムウォークする。
+/// 複数の初期値から始めるには `Composition::from_initials` と `random_walks_model` を使う
+pub fn random_walks<T>(
+    r0: &T,
+    ps: &[Process<T>],
+    config: &WalkConfig<State<T>>,
+) -> Result<WalkReport<State<T>>, Error>
+where
+    T: Debug + Hash + Eq + Clone + 'static,
+{
+    let model = Named(Composition::new(r0, ps)?);
+    random_walks_model(&model, config, String::from("---"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddsv::data::Trans;
+
+    fn is_unlocked_0(r: &(i32, i32)) -> bool {
+        r.0 == 0
+    }
+    fn is_unlocked_1(r: &(i32, i32)) -> bool {
+        r.1 == 0
+    }
+    fn always_true(_r: &(i32, i32)) -> bool {
+        true
+    }
+    fn lock_0(r: &(i32, i32)) -> (i32, i32) {
+        (1, r.1)
+    }
+    fn lock_1(r: &(i32, i32)) -> (i32, i32) {
+        (r.0, 1)
+    }
+    fn unlock_0(r: &(i32, i32)) -> (i32, i32) {
+        (0, r.1)
+    }
+    fn unlock_1(r: &(i32, i32)) -> (i32, i32) {
+        (r.0, 0)
+    }
+
+    fn processes() -> Vec<Process<(i32, i32)>> {
+        vec![
+            Process::new(vec![
+                (
+                    "P0",
+                    vec![Trans::new("lock 0", "P1", is_unlocked_0, lock_0)],
+                ),
+                (
+                    "P1",
+                    vec![Trans::new("lock 1", "P2", is_unlocked_1, lock_1)],
+                ),
+                (
+                    "P2",
+                    vec![Trans::new("unlock 1", "P3", always_true, unlock_1)],
+                ),
+                (
+                    "P3",
+                    vec![Trans::new("unlock 0", "P0", always_true, unlock_0)],
+                ),
+            ]),
+            Process::new(vec![
+                (
+                    "Q0",
+                    vec![Trans::new("lock 1", "Q1", is_unlocked_1, lock_1)],
+                ),
+                (
+                    "Q1",
+                    vec![Trans::new("lock 0", "Q2", is_unlocked_0, lock_0)],
+                ),
+                (
+                    "Q2",
+                    vec![Trans::new("unlock 0", "Q3", always_true, unlock_0)],
+                ),
+                (
+                    "Q3",
+                    vec![Trans::new("unlock 1", "Q0", always_true, unlock_1)],
+                ),
+            ]),
+        ]
+    }
+
+    #[test]
+    fn random_walks_find_deadlock_test() {
+        let config = WalkConfig {
+            seed: 42,
+            walks: 50,
+            max_depth: 100,
+            ..WalkConfig::default()
+        };
+        let report = random_walks(&(0, 0), &processes(), &config).unwrap();
+        assert_eq!(report.deadlocks.len(), 1);
+        let (r, locs) = &report.deadlocks[0].last().unwrap().1;
+        assert_eq!(r, &(1, 1));
+        assert_eq!(locs, &["P1", "Q1"]);
+        assert_eq!(report.final_states.values().sum::<usize>(), 50);
+    }
+
+    #[test]
+    fn random_walks_reproducible_test() {
+        let config = WalkConfig {
+            seed: 7,
+            walks: 20,
+            max_depth: 5,
+            ..WalkConfig::default()
+        };
+        let a = random_walks(&(0, 0), &processes(), &config).unwrap();
+        let b = random_walks(&(0, 0), &processes(), &config).unwrap();
+        assert_eq!(a.histogram(), b.histogram());
+        assert_eq!(a.deadlocks, b.deadlocks);
+    }
+
+    #[test]
+    fn random_walks_assertion_test() {
+        fn never_both_locked(s: &State<(i32, i32)>) -> bool {
+            s.0 != (1, 1)
+        }
+        fn prefer_lock(label: &Label, _s: &State<(i32, i32)>) -> u32 {
+            if label.starts_with("lock") {
+                1
+            } else {
+                0
+            }
+        }
+        let config = WalkConfig {
+            walks: 10,
+            weight: Some(prefer_lock),
+            assertion: Some(never_both_locked),
+            ..WalkConfig::default()
+        };
+        let report = random_walks(&(0, 0), &processes(), &config).unwrap();
+        assert!(report.deadlocks.is_empty());
+        assert!(!report.violations.is_empty());
+        for path in &report.violations {
+            assert_eq!(path.len(), 3);
+            assert_eq!((path[2].1).0, (1, 1));
+        }
+    }
+
+    // 初期状態 `start` から `start + 2` まで数えて止まるカウンタ
+    struct Counter(Vec<u32>);
+
+    impl Model for Counter {
+        type State = u32;
+        type Label = &'static str;
+
+        fn initial_states(&self) -> Vec<u32> {
+            self.0.clone()
+        }
+
+        fn successors(&self, s: &u32, buf: &mut Vec<Step<u32, &'static str>>) -> Result<(), Error> {
+            if s % 10 < 2 {
+                buf.push(("inc", Ok(s + 1)));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn random_walks_model_test() {
+        let config = WalkConfig {
+            walks: 20,
+            ..WalkConfig::default()
+        };
+        let report = random_walks_model(&Counter(vec![0, 10]), &config, "start").unwrap();
+        let mut ends = report.final_states.keys().copied().collect::<Vec<_>>();
+        ends.sort();
+        assert_eq!(ends, [2, 12]);
+        assert_eq!(report.deadlocks.len(), 2);
+        assert_eq!(report.deadlocks[0][0].0, "start");
+        assert_eq!(report.deadlocks[0].len(), 3);
+    }
+
+    #[test]
+    fn below_test() {
+        let mut rng = Rng::new(1);
+        let mut counts = [0; 3];
+        for _ in 0..300 {
+            counts[rng.below(3) as usize] += 1;
+        }
+        assert!(counts.iter().all(|n| *n > 50));
+        assert!((0..100).all(|_| rng.below(u64::MAX) < u64::MAX));
+    }
+
+    #[test]
+    #[should_panic(expected = "Rng::below requires n > 0")]
+    fn below_zero_test() {
+        Rng::new(1).below(0);
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::hash::Hash;
+
+use super::data::Lts;
+use super::Label;
+
+/// 詳細化検査の反例。`trace` は初期状態から違反箇所までの最短のラベル列
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Counterexample {
+    /// 実装は `trace` の後に `label` を実行できるが、仕様はできない
+    Trace { trace: Vec<Label>, label: Label },
+    /// 実装は `trace` の後に `offered` 以外をすべて拒否するが、仕様にはそのような拒否がない
+    Failure {
+        trace: Vec<Label>,
+        offered: Vec<Label>,
+    },
+}
+
+impl fmt::Display for Counterexample {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            Counterexample::Trace { trace, label } => write!(
+                f,
+                "trace violation: <{}> then \"{}\" is not allowed by the spec",
+                trace.join(", "),
+                label
+            ),
+            Counterexample::Failure { trace, offered } => write!(
+                f,
+                "failure violation: after <{}> the implementation offers only {{{}}}",
+                trace.join(", "),
+                offered.join(", ")
+            ),
+        }
+    }
+}
+
+/// LTSを状態IDで引ける隣接リストに変換する
+fn index<T>(lts: &Lts<T>) -> Vec<Vec<(Label, usize)>> {
+    lts.edges
+        .iter()
+        .map(|trans| {
+            trans
+                .iter()
+                .map(|(label, target)| (label.clone(), *target as usize))
+                .collect()
+        })
+        .collect()
+}
+
+fn initials(graph: &[Vec<(Label, usize)>], s: usize) -> BTreeSet<&Label> {
+    graph[s].iter().map(|(label, _)| label).collect()
+}
+
+/// トレース詳細化 (spec ⊑T imp) を検査する
+pub fn check_traces_refinement<S, I>(spec: &Lts<S>, imp: &Lts<I>) -> Result<(), Counterexample>
+where
+    S: Hash + Eq,
+    I: Hash + Eq,
+{
+    check(spec, imp, false)
+}
+
+/// 失敗詳細化 (spec ⊑F imp) を検査する。実装のデッドロックもここで検出される
+pub fn check_failures_refinement<S, I>(spec: &Lts<S>, imp: &Lts<I>) -> Result<(), Counterexample>
+where
+    S: Hash + Eq,
+    I: Hash + Eq,
+{
+    check(spec, imp, true)
+}
+
+fn check<S, I>(spec: &Lts<S>, imp: &Lts<I>, failures: bool) -> Result<(), Counterexample>
+where
+    S: Hash + Eq,
+    I: Hash + Eq,
+{
+    // 空のトレースでは仕様のどの初期状態にもいうるので、実装の初期状態ごとに仕様の初期状態すべてと組にする
+    let spec_initials = (0..spec.initials).collect::<BTreeSet<usize>>();
+    let imp_initials = 0..imp.initials;
+    let spec = index(spec);
+    let imp = index(imp);
+
+    // (実装の状態, 同じトレースで到達しうる仕様の状態集合) の組を幅優先で探索する
+    let mut visited: HashMap<(usize, BTreeSet<usize>), usize> = HashMap::new();
+    let mut parents: Vec<Option<(usize, Label)>> = vec![];
+    let mut que = VecDeque::new();
+    for i in imp_initials {
+        let start = (i, spec_initials.clone());
+        visited.insert(start.clone(), parents.len());
+        que.push_front((start, parents.len()));
+        parents.push(None);
+    }
+
+    let trace_to = |parents: &[Option<(usize, Label)>], mut n: usize| {
+        let mut trace = vec![];
+        while let Some((parent, label)) = &parents[n] {
+            trace.push(label.clone());
+            n = *parent;
+        }
+        trace.reverse();
+        trace
+    };
+
+    while let Some(((i, ss), n)) = que.pop_back() {
+        if failures {
+            let offered = initials(&imp, i);
+            if !ss.iter().any(|s| initials(&spec, *s).is_subset(&offered)) {
+                return Err(Counterexample::Failure {
+                    trace: trace_to(&parents, n),
+                    offered: offered.into_iter().cloned().collect(),
+                });
+            }
+        }
+        for (label, i_2) in &imp[i] {
+            let ss_2 = ss
+                .iter()
+                .flat_map(|s| spec[*s].iter())
+                .filter(|(l, _)| l == label)
+                .map(|(_, s_2)| *s_2)
+                .collect::<BTreeSet<usize>>();
+            if ss_2.is_empty() {
+                return Err(Counterexample::Trace {
+                    trace: trace_to(&parents, n),
+                    label: label.clone(),
+                });
+            }
+            let key = (*i_2, ss_2);
+            if !visited.contains_key(&key) {
+                let m = parents.len();
+                parents.push(Some((n, label.clone())));
+                visited.insert(key.clone(), m);
+                que.push_front((key, m));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddsv::data::{concurrent_composition, concurrent_composition_from, Process, Trans};
+
+    fn always_true(_r: &()) -> bool {
+        true
+    }
+    fn keep(_r: &()) {}
+
+    fn lts(p: Process<()>) -> Lts<()> {
+        concurrent_composition(&(), &[p]).unwrap()
+    }
+
+    fn spec() -> Process<()> {
+        Process::new(vec![
+            ("S0", vec![Trans::new("lock", "S1", always_true, keep)]),
+            ("S1", vec![Trans::new("unlock", "S0", always_true, keep)]),
+        ])
+    }
+
+    #[test]
+    fn refinement_holds_test() {
+        let imp = Process::new(vec![
+            ("P0", vec![Trans::new("lock", "P1", always_true, keep)]),
+            ("P1", vec![Trans::new("unlock", "P2", always_true, keep)]),
+            ("P2", vec![Trans::new("lock", "P1", always_true, keep)]),
+        ]);
+        assert_eq!(
+            check_traces_refinement(&lts(spec()), &lts(imp.clone())),
+            Ok(())
+        );
+        assert_eq!(check_failures_refinement(&lts(spec()), &lts(imp)), Ok(()));
+    }
+
+    #[test]
+    fn trace_violation_test() {
+        let imp = Process::new(vec![
+            ("P0", vec![Trans::new("lock", "P1", always_true, keep)]),
+            ("P1", vec![Trans::new("lock", "P0", always_true, keep)]),
+        ]);
+        assert_eq!(
+            check_traces_refinement(&lts(spec()), &lts(imp)),
+            Err(Counterexample::Trace {
+                trace: vec![String::from("lock")],
+                label: String::from("lock"),
+            })
+        );
+    }
+
+    #[test]
+    fn deadlock_is_failure_violation_test() {
+        let imp = Process::new(vec![
+            ("P0", vec![Trans::new("lock", "P1", always_true, keep)]),
+            ("P1", vec![Trans::new("unlock", "P2", always_true, keep)]),
+            ("P2", vec![]),
+        ]);
+        assert_eq!(
+            check_traces_refinement(&lts(spec()), &lts(imp.clone())),
+            Ok(())
+        );
+        assert_eq!(
+            check_failures_refinement(&lts(spec()), &lts(imp)),
+            Err(Counterexample::Failure {
+                trace: vec![String::from("lock"), String::from("unlock")],
+                offered: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn multiple_initials_test() {
+        fn is_0(r: &i32) -> bool {
+            *r == 0
+        }
+        fn is_1(r: &i32) -> bool {
+            *r == 1
+        }
+        fn same(r: &i32) -> i32 {
+            *r
+        }
+        // 共有変数が0なら "a"、1なら "b" を実行する
+        let branch = || {
+            Process::new(vec![
+                (
+                    "P0",
+                    vec![
+                        Trans::new("a", "P1", is_0, same),
+                        Trans::new("b", "P1", is_1, same),
+                    ],
+                ),
+                ("P1", vec![]),
+            ])
+        };
+        let both = concurrent_composition_from(&[0, 1], &[branch()]).unwrap();
+        let only_a = concurrent_composition(&0, &[branch()]).unwrap();
+        let only_b = concurrent_composition(&1, &[branch()]).unwrap();
+
+        // 仕様のどちらの初期状態から始めてもよい
+        assert_eq!(check_traces_refinement(&both, &only_a), Ok(()));
+        assert_eq!(check_traces_refinement(&both, &only_b), Ok(()));
+        // 実装のすべての初期状態から検査する
+        assert_eq!(
+            check_traces_refinement(&only_a, &both),
+            Err(Counterexample::Trace {
+                trace: vec![],
+                label: String::from("b"),
+            })
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use super::data::{format_step, Network, Process};
+use super::error::Error;
+use super::simulator::enabled_at;
+use super::{Label, Path, State};
+
+/// 再生するトレースの1ステップ。`process` を省略するとどのプロセスの遷移でもよい。
+/// 任意の `Model` のトレースは `data::model_lts` でLTSにして `Lts::trace_to` で辿る
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    pub process: Option<usize>,
+    pub label: Label,
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self.process {
+            Some(p) => write!(f, "{}.{}", p, self.label),
+            None => write!(f, "{}", self.label),
+        }
+    }
+}
+
+/// トレースの再生の失敗
+#[derive(Debug)]
+pub enum ReplayError<T> {
+    /// `step` 番目のステップがどの状態からも実行できない。`reached` はその直前までに到達した状態へのトレース
+    NotEnabled {
+        step: usize,
+        expected: String,
+        reached: Vec<Path<T>>,
+    },
+    /// モデルの誤りなどで再生を始められない
+    Model(Error),
+}
+
+impl<T> From<Error> for ReplayError<T> {
+    fn from(e: Error) -> Self {
+        ReplayError::Model(e)
+    }
+}
+
+impl<T> fmt::Display for ReplayError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ReplayError::NotEnabled {
+                step,
+                expected,
+                reached,
+            } => write!(
+                f,
+                "step {} ({}) is not enabled in any of {} reached state(s)",
+                step,
+                expected,
+                reached.len()
+            ),
+            ReplayError::Model(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// "P.lock, P.read, Q.lock" のような文字列をステップ列に変換する。
+/// `.` の前が `names` のいずれか、または数字ならプロセスの指定とみなす
+pub fn parse_trace(s: &str, names: &[&str]) -> Vec<Step> {
+    s.split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            if let Some((name, label)) = item.split_once('.') {
+                let process = names
+                    .iter()
+                    .position(|n| *n == name)
+                    .or_else(|| name.parse().ok());
+                if process.is_some() {
+                    return Step {
+                        process,
+                        label: label.to_string(),
+                    };
+                }
+            }
+            Step {
+                process: None,
+                label: item.to_string(),
+            }
+        })
+        .collect()
+}
+
+// `frontier` の各状態から `matches` を満たす遷移で1歩進める。到達先が同じトレースは最初の1つだけ残す
+fn advance<T, F>(net: &Network<T>, frontier: &[Path<T>], matches: F) -> Result<Vec<Path<T>>, Error>
+where
+    T: Hash + Eq + Clone,
+    F: Fn(usize, &Label, &State<T>) -> bool,
+{
+    let mut seen = HashSet::new();
+    let mut paths = vec![];
+    for path in frontier {
+        for e in enabled_at(net, &path.last().unwrap().1)? {
+            // 失敗する遷移は遷移先がないので辿らない
+            let target = match e.target {
+                Ok(target) => target,
+                Err(_) => continue,
+            };
+            if matches(e.process, &e.label, &target) && seen.insert(target.clone()) {
+                let mut p = path.clone();
+                p.push((e.label, target));
+                paths.push(p);
+            }
+        }
+    }
+    Ok(paths)
+}
+
+/// 共有変数が `r0` の初期状態からステップ列を再生する。ラベルが複数の遷移に一致する場合はすべての分岐を追い、
+/// 最後まで再生できたトレースを返す。初期状態は1つだけで、複数の初期値は初期値ごとに再生する
+pub fn replay<T>(r0: &T, ps: &[Process<T>], steps: &[Step]) -> Result<Vec<Path<T>>, ReplayError<T>>
+where
+    T: Debug + Hash + Eq + Clone + 'static,
+{
+    let net = Network::new(ps)?;
+    let s0 = net.unpack(&net.initial_state(r0));
+    let mut frontier = vec![vec![(String::from("---"), s0)]];
+    for (i, step) in steps.iter().enumerate() {
+        let paths = advance(&net, &frontier, |p, label, _| {
+            *label == step.label && step.process.is_none_or(|q| q == p)
+        })?;
+        if paths.is_empty() {
+            return Err(ReplayError::NotEnabled {
+                step: i + 1,
+                expected: step.to_string(),
+                reached: frontier,
+            });
+        }
+        frontier = paths;
+    }
+    Ok(frontier)
+}
+
+/// `print_deadlock` の出力を共有変数が `r0` の初期状態から再生する。各行の状態まで一致する遷移だけを辿る。
+/// `-` だけの区切り行と空行は読み飛ばし、最初のトレースだけを対象にする
+pub fn replay_printed<T>(r0: &T, ps: &[Process<T>], text: &str) -> Result<Path<T>, ReplayError<T>>
+where
+    T: Debug + Hash + Eq + Clone + 'static,
+{
+    let net = Network::new(ps)?;
+    let s0 = net.unpack(&net.initial_state(r0));
+    let mut lines = text
+        .lines()
+        .map(|l| l.trim_end())
+        .skip_while(|l| l.is_empty() || l.chars().all(|c| c == '-'))
+        .take_while(|l| !l.is_empty() && !l.chars().all(|c| c == '-'));
+
+    let first = lines.next().unwrap_or_default();
+    if first != format_step(0, "---", &s0).trim_end() {
+        return Err(ReplayError::NotEnabled {
+            step: 0,
+            expected: first.to_string(),
+            reached: vec![],
+        });
+    }
+    let mut frontier = vec![vec![(String::from("---"), s0)]];
+    for (i, line) in lines.enumerate() {
+        let paths = advance(&net, &frontier, |_, label, target| {
+            format_step(i + 1, label, target).trim_end() == line
+        })?;
+        if paths.is_empty() {
+            return Err(ReplayError::NotEnabled {
+                step: i + 1,
+                expected: line.to_string(),
+                reached: frontier,
+            });
+        }
+        frontier = paths;
+    }
+    Ok(frontier.swap_remove(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddsv::data::{concurrent_composition, Trans};
+
+    fn always_true(_r: &i32) -> bool {
+        true
+    }
+    fn read(r: &i32) -> i32 {
+        *r
+    }
+    fn inc(r: &i32) -> i32 {
+        r + 1
+    }
+
+    fn processes() -> Vec<Process<i32>> {
+        ["P", "Q"]
+            .iter()
+            .map(|p| {
+                let l = (0..3).map(|i| format!("{}{}", p, i)).collect::<Vec<_>>();
+                Process::new(vec![
+                    (
+                        &l[0],
+                        vec![
+                            Trans::new("read", &l[1], always_true, read),
+                            Trans::new("inc", &l[2], always_true, inc),
+                        ],
+                    ),
+                    (&l[1], vec![Trans::new("inc", &l[2], always_true, inc)]),
+                    (&l[2], vec![]),
+                ])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parse_trace_test() {
+        let steps = parse_trace("P.lock, Q.read, 1.inc, lock 0, x.y", &["P", "Q"]);
+        let expected = [
+            (Some(0), "lock"),
+            (Some(1), "read"),
+            (Some(1), "inc"),
+            (None, "lock 0"),
+            (None, "x.y"),
+        ];
+        assert_eq!(steps.len(), expected.len());
+        for (s, (p, l)) in steps.iter().zip(expected.iter()) {
+            assert_eq!((s.process, s.label.as_str()), (*p, *l));
+        }
+    }
+
+    #[test]
+    fn replay_branching_test() {
+        // ラベルだけの "inc" は P と Q のどちらの遷移にも一致する
+        let paths = replay(&0, &processes(), &parse_trace("inc", &[])).unwrap();
+        assert_eq!(paths.len(), 2);
+        let paths = replay(&0, &processes(), &parse_trace("read, Q.inc", &["P", "Q"])).unwrap();
+        assert_eq!(paths.len(), 2);
+        assert_eq!((paths[0].last().unwrap().1).1, ["P1", "Q2"]);
+        assert_eq!((paths[1].last().unwrap().1).1, ["P0", "Q2"]);
+    }
+
+    #[test]
+    fn replay_error_test() {
+        let err = replay(&0, &processes(), &parse_trace("P.inc, P.read", &["P", "Q"])).unwrap_err();
+        match err {
+            ReplayError::NotEnabled {
+                step,
+                expected,
+                reached,
+            } => {
+                assert_eq!(step, 2);
+                assert_eq!(expected, "0.read");
+                assert_eq!(reached.len(), 1);
+            }
+            ReplayError::Model(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn replay_printed_test() {
+        let lts = concurrent_composition(&0, &processes()).unwrap();
+        let deadlock = lts.trace_to(*lts.deadlocks.last().unwrap()).unwrap();
+        let text = deadlock
+            .iter()
+            .enumerate()
+            .map(|(i, (label, state))| format_step(i, label, state) + "\n")
+            .collect::<String>();
+        let text = format!("--------------------------------------\n{}", text);
+        let path = replay_printed(&0, &processes(), &text).unwrap();
+        assert_eq!(path, deadlock);
+
+        let broken = text.replace("P2", "P9");
+        assert!(replay_printed(&0, &processes(), &broken).is_err());
+    }
+}
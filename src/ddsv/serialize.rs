@@ -0,0 +1,275 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::Write;
+
+use super::data::{lts_deadlock_ids, lts_states, Lts, Process};
+use super::error::Error;
+
+fn json_str(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_list<I>(items: I) -> String
+where
+    I: IntoIterator<Item = String>,
+{
+    format!("[{}]", items.into_iter().collect::<Vec<_>>().join(","))
+}
+
+fn xml_str(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// 初期状態から `id` の状態までのトレースを (ラベル, ID) の列で書く
+fn json_trace<T>(lts: &Lts<T>, id: i32) -> String {
+    json_list(
+        lts.steps_to(id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(label, id)| format!("{{\"label\":{},\"state\":{}}}", json_str(label), id)),
+    )
+}
+
+/// プロセスの場所と遷移をJSONで書き出す
+pub fn process_to_json<T>(ch: &mut dyn Write, process: &Process<T>) -> Result<(), Error> {
+    let locations = json_list(process.0.iter().map(|(location, trans)| {
+        let trans = json_list(trans.iter().map(|t| {
+            format!(
+                "{{\"label\":{},\"target\":{}}}",
+                json_str(&t.label),
+                json_str(&t.location)
+            )
+        }));
+        format!(
+            "{{\"name\":{},\"transitions\":{}}}",
+            json_str(location),
+            trans
+        )
+    }));
+    writeln!(ch, "{{\"locations\":{}}}", locations)?;
+    Ok(())
+}
+
+/// LTSの状態・遷移・デッドロックへのトレース・遷移の失敗をJSONで書き出す
+pub fn lts_to_json<T>(ch: &mut dyn Write, lts: &Lts<T>) -> Result<(), Error>
+where
+    T: Debug + Hash + Eq + Clone,
+{
+    let deadlock_ids = lts_deadlock_ids(lts);
+    let nodes = json_list(lts_states(lts).map(|((r, locs), id, _)| {
+        format!(
+            "{{\"id\":{},\"locations\":{},\"vars\":{},\"initial\":{},\"deadlock\":{}}}",
+            id,
+            json_list(lts.location_names(locs).iter().map(|l| json_str(l))),
+            json_str(&format!("{:?}", r)),
+            lts.is_initial(id),
+            deadlock_ids.contains(&id)
+        )
+    }));
+    let edges = json_list(lts_states(lts).flat_map(|(_, id, trans)| {
+        trans.iter().map(move |(label, target)| {
+            format!(
+                "{{\"source\":{},\"target\":{},\"label\":{}}}",
+                id,
+                target,
+                json_str(label)
+            )
+        })
+    }));
+    let deadlocks = json_list(lts.deadlocks.iter().map(|id| json_trace(lts, *id)));
+    let violations = json_list(lts.violations.iter().map(|v| {
+        format!(
+            "{{\"label\":{},\"message\":{},\"trace\":{}}}",
+            json_str(&v.label),
+            json_str(&v.message),
+            json_trace(lts, v.state)
+        )
+    }));
+    writeln!(
+        ch,
+        "{{\"states\":{},\"transitions\":{},\"deadlocks\":{},\"violations\":{}}}",
+        nodes, edges, deadlocks, violations
+    )?;
+    Ok(())
+}
+
+fn graphml_header(ch: &mut dyn Write, keys: &[(&str, &str, &str)]) -> Result<(), Error> {
+    writeln!(ch, "<?xml version=\"1.0\" encoding=\"UTF-8\"?>")?;
+    writeln!(
+        ch,
+        "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">"
+    )?;
+    for (id, domain, ty) in keys {
+        writeln!(
+            ch,
+            "  <key id=\"{}\" for=\"{}\" attr.name=\"{}\" attr.type=\"{}\"/>",
+            id, domain, id, ty
+        )?;
+    }
+    writeln!(ch, "  <graph edgedefault=\"directed\">")?;
+    Ok(())
+}
+
+fn graphml_footer(ch: &mut dyn Write) -> Result<(), Error> {
+    writeln!(ch, "  </graph>")?;
+    writeln!(ch, "</graphml>")?;
+    Ok(())
+}
+
+/// プロセスの場所と遷移をGraphMLで書き出す
+pub fn process_to_graphml<T>(ch: &mut dyn Write, process: &Process<T>) -> Result<(), Error> {
+    graphml_header(ch, &[("label", "edge", "string")])?;
+    for (location, _) in &process.0 {
+        writeln!(ch, "    <node id=\"{}\"/>", xml_str(location))?;
+    }
+    for (location, trans) in &process.0 {
+        for t in trans {
+            writeln!(
+                ch,
+                "    <edge source=\"{}\" target=\"{}\"><data key=\"label\">{}</data></edge>",
+                xml_str(location),
+                xml_str(&t.location),
+                xml_str(&t.label)
+            )?;
+        }
+    }
+    graphml_footer(ch)
+}
+
+/// LTSをGraphMLで書き出す。デッドロックへのトレースは `trace` 属性に入れる
+pub fn lts_to_graphml<T>(ch: &mut dyn Write, lts: &Lts<T>) -> Result<(), Error>
+where
+    T: Debug + Hash + Eq + Clone,
+{
+    let deadlock_ids = lts_deadlock_ids(lts);
+    graphml_header(
+        ch,
+        &[
+            ("locations", "node", "string"),
+            ("vars", "node", "string"),
+            ("initial", "node", "boolean"),
+            ("deadlock", "node", "boolean"),
+            ("trace", "node", "string"),
+            ("label", "edge", "string"),
+        ],
+    )?;
+    for ((r, locs), id, _) in lts_states(lts) {
+        writeln!(ch, "    <node id=\"s{}\">", id)?;
+        writeln!(
+            ch,
+            "      <data key=\"locations\">{}</data>",
+            xml_str(&lts.location_names(locs).join(" "))
+        )?;
+        writeln!(
+            ch,
+            "      <data key=\"vars\">{}</data>",
+            xml_str(&format!("{:?}", r))
+        )?;
+        writeln!(
+            ch,
+            "      <data key=\"initial\">{}</data>",
+            lts.is_initial(id)
+        )?;
+        writeln!(
+            ch,
+            "      <data key=\"deadlock\">{}</data>",
+            deadlock_ids.contains(&id)
+        )?;
+        if deadlock_ids.contains(&id) {
+            writeln!(
+                ch,
+                "      <data key=\"trace\">{}</data>",
+                xml_str(&json_trace(lts, id))
+            )?;
+        }
+        writeln!(ch, "    </node>")?;
+    }
+    for (_, id, trans) in lts_states(lts) {
+        for (label, target) in trans.iter() {
+            writeln!(
+                ch,
+                "    <edge source=\"s{}\" target=\"s{}\"><data key=\"label\">{}</data></edge>",
+                id,
+                target,
+                xml_str(label)
+            )?;
+        }
+    }
+    graphml_footer(ch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddsv::data::{concurrent_composition, Trans};
+
+    fn always_true(_r: &i32) -> bool {
+        true
+    }
+    fn inc(r: &i32) -> i32 {
+        r + 1
+    }
+
+    fn process() -> Process<i32> {
+        Process::new(vec![
+            ("P0", vec![Trans::new("inc", "P1", always_true, inc)]),
+            ("P1", vec![]),
+        ])
+    }
+
+    #[test]
+    fn process_to_json_test() {
+        let mut out = vec![];
+        process_to_json(&mut out, &process()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"locations\":[{\"name\":\"P0\",\"transitions\":[{\"label\":\"inc\",\"target\":\"P1\"}]},\
+             {\"name\":\"P1\",\"transitions\":[]}]}\n"
+        );
+    }
+
+    #[test]
+    fn lts_to_json_test() {
+        let lts = concurrent_composition(&0, &[process()]).unwrap();
+        let mut out = vec![];
+        lts_to_json(&mut out, &lts).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"states\":[\
+             {\"id\":0,\"locations\":[\"P0\"],\"vars\":\"0\",\"initial\":true,\"deadlock\":false},\
+             {\"id\":1,\"locations\":[\"P1\"],\"vars\":\"1\",\"initial\":false,\"deadlock\":true}],\
+             \"transitions\":[{\"source\":0,\"target\":1,\"label\":\"inc\"}],\
+             \"deadlocks\":[[{\"label\":\"---\",\"state\":0},{\"label\":\"inc\",\"state\":1}]],\
+             \"violations\":[]}\n"
+        );
+    }
+
+    #[test]
+    fn lts_to_graphml_test() {
+        let lts = concurrent_composition(&0, &[process()]).unwrap();
+        let mut out = vec![];
+        lts_to_graphml(&mut out, &lts).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("<node id=\"s1\">"));
+        assert!(out.contains("<data key=\"deadlock\">true</data>"));
+        assert!(
+            out.contains("<edge source=\"s0\" target=\"s1\"><data key=\"label\">inc</data></edge>")
+        );
+    }
+}
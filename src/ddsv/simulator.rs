@@ -0,0 +1,365 @@
+use std::fmt::Debug;
+use std::io::{self, BufRead, Write};
+
+use super::data::{calc_transitions, format_step, print_locations, Network, Process};
+use super::error::Error;
+use super::{Label, Path, State};
+
+/// 現在の状態から実行可能な遷移。`target` が `Err` なら遷移は失敗し、その理由を持つ
+#[derive(Debug, Clone, PartialEq)]
+pub struct Enabled<T> {
+    pub process: usize,
+    pub label: Label,
+    pub target: Result<State<T>, String>,
+}
+
+/// `Simulator::step` の結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stepped {
+    /// 遷移して状態が進んだ
+    Moved,
+    /// 遷移が失敗した。状態は進まない
+    Failed(String),
+    /// 指定した番号の遷移がない
+    NotEnabled,
+}
+
+/// `state` から実行可能な遷移を、遷移するプロセスの番号と一緒に返す
+pub fn enabled_at<T>(net: &Network<T>, state: &State<T>) -> Result<Vec<Enabled<T>>, Error>
+where
+    T: Clone,
+{
+    let s = net.pack(state)?;
+    let mut enabled = vec![];
+    // プロセスごとに遷移を計算するので、非決定的な遷移が複数の遷移先を返しても番号がずれない。
+    // `make_next_function` はすべてのプロセスの遷移をまとめて返し、どのプロセスの遷移かが分からないので使わない。
+    // どちらも同じ `push_transitions` で遷移を作るので、探索とシミュレータで遷移は変わらない。
+    // 失敗する遷移も選択肢に含め、選ぶと失敗を報告する
+    for i in 0..net.processes().len() {
+        let trans = calc_transitions(vec![], net, &s, i);
+        enabled.extend(trans.into_iter().map(|(label, target)| Enabled {
+            process: i,
+            label,
+            target: target.map(|target| net.unpack(&target)),
+        }));
+    }
+    Ok(enabled)
+}
+
+/// モデルを1遷移ずつ手で動かすためのシミュレータ。遷移をプロセスごとに選ぶので、
+/// `Model` ではなくプロセスの並行合成だけを扱う
+pub struct Simulator<T> {
+    net: Network<T>,
+    history: Path<T>,
+}
+
+impl<T> Simulator<T>
+where
+    T: Debug + Clone,
+{
+    /// 共有変数が `r0` の初期状態から始める。初期状態は1つだけなので、複数の初期値を試すには
+    /// 初期値ごとに作るか `from_state` を使う
+    pub fn new(r0: &T, ps: &[Process<T>]) -> Result<Simulator<T>, Error> {
+        let net = Network::new(ps)?;
+        let s0 = net.unpack(&net.initial_state(r0));
+        Ok(Simulator::start(net, s0))
+    }
+
+    /// 場所を名前で表した任意の状態 `s0` から始める
+    pub fn from_state(s0: &State<T>, ps: &[Process<T>]) -> Result<Simulator<T>, Error> {
+        let net = Network::new(ps)?;
+        net.pack(s0)?;
+        Ok(Simulator::start(net, s0.clone()))
+    }
+
+    fn start(net: Network<T>, s0: State<T>) -> Simulator<T> {
+        Simulator {
+            net,
+            history: vec![(String::from("---"), s0)],
+        }
+    }
+
+    pub fn current(&self) -> &State<T> {
+        &self.history.last().unwrap().1
+    }
+
+    /// これまでに辿った (ラベル, 状態) の列。先頭は初期状態
+    pub fn history(&self) -> &Path<T> {
+        &self.history
+    }
+
+    pub fn enabled(&self) -> Result<Vec<Enabled<T>>, Error> {
+        enabled_at(&self.net, self.current())
+    }
+
+    /// `enabled()` の `n` 番目の遷移を実行する。遷移が失敗するか範囲外なら状態は変わらない
+    pub fn step(&mut self, n: usize) -> Result<Stepped, Error> {
+        match self.enabled()?.into_iter().nth(n) {
+            Some(Enabled {
+                label,
+                target: Ok(target),
+                ..
+            }) => {
+                self.history.push((label, target));
+                Ok(Stepped::Moved)
+            }
+            Some(Enabled {
+                target: Err(message),
+                ..
+            }) => Ok(Stepped::Failed(message)),
+            None => Ok(Stepped::NotEnabled),
+        }
+    }
+
+    /// 直前の遷移を取り消す。初期状態では何もせず `false` を返す
+    pub fn undo(&mut self) -> bool {
+        if self.history.len() > 1 {
+            self.history.pop();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// `step` 番目 (0が初期状態) の状態まで戻る
+    pub fn jump(&mut self, step: usize) -> bool {
+        if step < self.history.len() {
+            self.history.truncate(step + 1);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// `input` から1行ずつコマンドを読んで対話的に実行する。
+    /// 番号で遷移を選択、`u` で取り消し、`j <n>` で `n` 番目の状態に戻る、`h` で履歴表示、`q` で終了
+    pub fn run(&mut self, input: &mut dyn BufRead, out: &mut dyn Write) -> Result<(), Error> {
+        loop {
+            self.print_current(out)?;
+            out.write_all("> ".as_bytes())?;
+            out.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            let words = line.split_whitespace().collect::<Vec<_>>();
+            match words.as_slice() {
+                [] => {}
+                ["q"] => return Ok(()),
+                ["u"] => {
+                    if !self.undo() {
+                        writeln!(out, "already at the initial state")?;
+                    }
+                }
+                ["j", n] => match n.parse() {
+                    Ok(n) if self.jump(n) => {}
+                    _ => writeln!(out, "no such step: {}", n)?,
+                },
+                ["h"] => self.print_history(out)?,
+                [n] => match n.parse().map(|i| self.step(i)) {
+                    Ok(Ok(Stepped::Moved)) => {}
+                    Ok(Ok(Stepped::Failed(message))) => {
+                        writeln!(out, "transition failed: {}", message)?
+                    }
+                    Ok(Err(e)) => return Err(e),
+                    _ => writeln!(out, "no such transition: {}", n)?,
+                },
+                _ => writeln!(out, "unknown command: {}", line.trim())?,
+            }
+        }
+    }
+
+    fn print_current(&self, out: &mut dyn Write) -> Result<(), Error> {
+        let (r, locs) = self.current();
+        write!(out, "step {}: {:?} ", self.history.len() - 1, r)?;
+        print_locations(out, locs)?;
+        writeln!(out)?;
+        let enabled = self.enabled()?;
+        if enabled.is_empty() {
+            writeln!(out, "  (no enabled transitions)")?;
+        }
+        for (i, e) in enabled.iter().enumerate() {
+            write!(out, "  {}) process {}: {}", i, e.process, e.label)?;
+            if let Err(message) = &e.target {
+                write!(out, " (fails: {})", message)?;
+            }
+            writeln!(out)?;
+        }
+        Ok(())
+    }
+
+    fn print_history(&self, out: &mut dyn Write) -> io::Result<()> {
+        for (i, (label, state)) in self.history.iter().enumerate() {
+            writeln!(out, "{}", format_step(i, label, state))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddsv::data::{make_next_function, Trans};
+
+    fn is_unlocked(r: &i32) -> bool {
+        *r == 0
+    }
+    fn always_true(_r: &i32) -> bool {
+        true
+    }
+    fn lock(_r: &i32) -> i32 {
+        1
+    }
+    fn unlock(_r: &i32) -> i32 {
+        0
+    }
+
+    fn processes() -> Vec<Process<i32>> {
+        ["P", "Q"]
+            .iter()
+            .map(|p| {
+                let (l0, l1) = (format!("{}0", p), format!("{}1", p));
+                Process::new(vec![
+                    (&l0, vec![Trans::new("lock", &l1, is_unlocked, lock)]),
+                    (&l1, vec![Trans::new("unlock", &l0, always_true, unlock)]),
+                ])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn step_undo_jump_test() {
+        let mut sim = Simulator::new(&0, &processes()).unwrap();
+        let enabled = sim.enabled().unwrap();
+        assert_eq!(enabled.len(), 2);
+        assert_eq!((enabled[1].process, enabled[1].label.as_str()), (1, "lock"));
+
+        assert_eq!(sim.step(1).unwrap(), Stepped::Moved);
+        assert_eq!(
+            sim.current(),
+            &(1, vec![String::from("P0"), String::from("Q1")])
+        );
+        let enabled = sim.enabled().unwrap();
+        assert_eq!(enabled.len(), 1);
+        assert_eq!(
+            (enabled[0].process, enabled[0].label.as_str()),
+            (1, "unlock")
+        );
+        assert_eq!(sim.step(1).unwrap(), Stepped::NotEnabled);
+
+        assert_eq!(sim.step(0).unwrap(), Stepped::Moved);
+        assert_eq!(sim.step(0).unwrap(), Stepped::Moved);
+        assert_eq!(sim.history().len(), 4);
+        assert!(sim.undo());
+        assert_eq!(sim.history().len(), 3);
+        assert!(sim.jump(0));
+        assert_eq!(
+            sim.current(),
+            &(0, vec![String::from("P0"), String::from("Q0")])
+        );
+        assert!(!sim.undo());
+        assert!(!sim.jump(1));
+    }
+
+    #[test]
+    fn enabled_choice_test() {
+        fn flip(_r: &i32) -> Vec<i32> {
+            vec![0, 1]
+        }
+        let mut ps = processes();
+        ps.insert(
+            0,
+            Process::new(vec![
+                ("C0", vec![Trans::choice("flip", "C1", always_true, flip)]),
+                ("C1", vec![]),
+            ]),
+        );
+        let net = Network::new(&ps).unwrap();
+        let s0 = (
+            0,
+            vec![String::from("C0"), String::from("P0"), String::from("Q0")],
+        );
+        let enabled = enabled_at(&net, &s0).unwrap();
+        let owners = enabled
+            .iter()
+            .map(|e| (e.process, e.label.as_str(), e.target.as_ref().unwrap().0))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            owners,
+            [
+                (0, "flip", 0),
+                (0, "flip", 1),
+                (1, "lock", 1),
+                (2, "lock", 1)
+            ]
+        );
+
+        // 探索の遷移関数と同じ遷移を同じ順に返す
+        let mut buf = vec![];
+        make_next_function(Network::new(&ps).unwrap())(&net.pack(&s0).unwrap(), &mut buf).unwrap();
+        let next = buf
+            .into_iter()
+            .map(|(label, target)| (label, target.map(|t| net.unpack(&t))))
+            .collect::<Vec<_>>();
+        let steps = enabled
+            .into_iter()
+            .map(|e| (e.label, e.target))
+            .collect::<Vec<_>>();
+        assert_eq!(steps, next);
+    }
+
+    #[test]
+    fn from_state_test() {
+        let s0 = (1, vec![String::from("P1"), String::from("Q0")]);
+        let sim = Simulator::from_state(&s0, &processes()).unwrap();
+        assert_eq!(sim.current(), &s0);
+        assert_eq!(sim.enabled().unwrap().len(), 1);
+        let unknown = (0, vec![String::from("P9"), String::from("Q0")]);
+        assert!(Simulator::from_state(&unknown, &processes()).is_err());
+    }
+
+    #[test]
+    fn failing_check_test() {
+        fn is_zero(r: &i32) -> Result<i32, String> {
+            if *r == 0 {
+                Ok(*r)
+            } else {
+                Err(format!("r = {}", r))
+            }
+        }
+        let mut ps = processes();
+        ps.push(Process::new(vec![
+            (
+                "C0",
+                vec![Trans::check("check", "C1", always_true, is_zero)],
+            ),
+            ("C1", vec![]),
+        ]));
+        let mut sim = Simulator::new(&0, &ps).unwrap();
+        assert_eq!(sim.step(0).unwrap(), Stepped::Moved);
+        let enabled = sim.enabled().unwrap();
+        assert_eq!(enabled.len(), 2);
+        assert_eq!(enabled[1].target, Err(String::from("r = 1")));
+        assert_eq!(sim.step(1).unwrap(), Stepped::Failed(String::from("r = 1")));
+        assert_eq!(sim.history().len(), 2);
+
+        let mut out = vec![];
+        sim.run(&mut "1\nq\n".as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("  1) process 2: check (fails: r = 1)\n"));
+        assert!(out.contains("transition failed: r = 1\n"));
+    }
+
+    #[test]
+    fn run_test() {
+        let mut sim = Simulator::new(&0, &processes()).unwrap();
+        let mut out = vec![];
+        sim.run(&mut "0\n5\nu\n1\nq\n".as_bytes(), &mut out)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("step 0: 0 P0 Q0 \n  0) process 0: lock\n  1) process 1: lock\n> "));
+        assert!(out.contains("no such transition: 5"));
+        assert_eq!(sim.history().len(), 2);
+        assert_eq!(sim.current().1, vec!["P0", "Q1"]);
+    }
+}
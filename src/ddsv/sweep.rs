@@ -0,0 +1,146 @@
+use std::fmt::Display;
+use std::hash::Hash;
+use std::io::Write;
+
+use super::error::Error;
+use super::model::{explore_with_limit, Model};
+
+/// パラメータの1つの値についての探索結果の集計
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SweepRow<P> {
+    pub param: P,
+    pub states: usize,
+    pub transitions: usize,
+    pub deadlocks: usize,
+    /// 遷移の失敗の数
+    pub failures: usize,
+}
+
+/// `params` の値ごとに `build` でモデルを作り直して探索し、状態数・遷移数・デッドロック数を集計する
+pub fn sweep<P, I, M, F>(params: I, build: F) -> Result<Vec<SweepRow<P>>, Error>
+where
+    I: IntoIterator<Item = P>,
+    F: Fn(&P) -> Result<M, Error>,
+    M: Model,
+    M::State: Hash + Eq + Clone,
+{
+    sweep_with_limit(params, build, usize::MAX)
+}
+
+/// どれかの値で状態数が `max_states` を超えたら `Error::LimitExceeded` を返す
+pub fn sweep_with_limit<P, I, M, F>(
+    params: I,
+    build: F,
+    max_states: usize,
+) -> Result<Vec<SweepRow<P>>, Error>
+where
+    I: IntoIterator<Item = P>,
+    F: Fn(&P) -> Result<M, Error>,
+    M: Model,
+    M::State: Hash + Eq + Clone,
+{
+    params
+        .into_iter()
+        .map(|param| {
+            let space = explore_with_limit(&build(&param)?, max_states)?;
+            Ok(SweepRow {
+                states: space.states.len(),
+                transitions: space.edges.iter().map(|e| e.len()).sum(),
+                deadlocks: space.deadlocks.len(),
+                failures: space.failures.len(),
+                param,
+            })
+        })
+        .collect()
+}
+
+/// 集計を表にして書き出す。`name` はパラメータの列の見出し
+pub fn print_sweep<P>(ch: &mut dyn Write, name: &str, rows: &[SweepRow<P>]) -> Result<(), Error>
+where
+    P: Display,
+{
+    let width = rows
+        .iter()
+        .map(|r| r.param.to_string().len())
+        .chain(std::iter::once(name.len()))
+        .max()
+        .unwrap_or(0);
+    writeln!(
+        ch,
+        "{:<w$}  {:>8}  {:>11}  {:>9}  {:>8}",
+        name,
+        "states",
+        "transitions",
+        "deadlocks",
+        "failures",
+        w = width
+    )?;
+    for r in rows {
+        writeln!(
+            ch,
+            "{:<w$}  {:>8}  {:>11}  {:>9}  {:>8}",
+            r.param.to_string(),
+            r.states,
+            r.transitions,
+            r.deadlocks,
+            r.failures,
+            w = width
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddsv::data::{Composition, Process, Trans};
+
+    // (個数, 上限)。上限は初期状態に入れておく
+    fn can_put(r: &(i32, i32)) -> bool {
+        r.0 < r.1
+    }
+    fn is_full(r: &(i32, i32)) -> bool {
+        r.0 == r.1
+    }
+    fn put(r: &(i32, i32)) -> (i32, i32) {
+        (r.0 + 1, r.1)
+    }
+    fn return_copied(r: &(i32, i32)) -> (i32, i32) {
+        *r
+    }
+
+    fn processes() -> Vec<Process<(i32, i32)>> {
+        vec![Process::new(vec![
+            (
+                "P0",
+                vec![
+                    Trans::new("put", "P0", can_put, put),
+                    Trans::new("done", "P1", is_full, return_copied),
+                ],
+            ),
+            ("P1", vec![]),
+        ])]
+    }
+
+    #[test]
+    fn sweep_test() {
+        let rows = sweep(1..=3, |max| Composition::new(&(0, *max), &processes())).unwrap();
+        let counts = rows
+            .iter()
+            .map(|r| (r.param, r.states, r.transitions, r.deadlocks))
+            .collect::<Vec<_>>();
+        assert_eq!(counts, [(1, 3, 2, 1), (2, 4, 3, 1), (3, 5, 4, 1)]);
+
+        let mut out = vec![];
+        print_sweep(&mut out, "MAX_COUNT", &rows[..1]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "MAX_COUNT    states  transitions  deadlocks  failures\n\
+             1                 3            2          1         0\n"
+        );
+
+        let err = sweep_with_limit(1..=3, |max| Composition::new(&(0, *max), &processes()), 4)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "state space exceeds the limit of 4 states");
+    }
+}
@@ -0,0 +1,961 @@
+// 共有変数に置いて使う同期プリミティブのモデル。
+// 共有変数の型に `Has<プリミティブ, 番号>` を実装すると、各モジュールのガードとアクションを
+// `Trans` にそのまま渡せる。`P` は操作するプロセスの番号 (`ps` での位置) で、
+// 持ち主のいるプリミティブを持ち主以外が解放すると遷移の失敗になる。
+// `*_trans` は `"<操作> <名前>"` というラベルの遷移を作る
+use std::collections::BTreeSet;
+
+use super::data::Trans;
+
+/// 共有変数の中の `ID` 番目のプリミティブ `P`。同じ種類のプリミティブを番号で区別する
+pub trait Has<P, const ID: usize>: Clone {
+    fn get(&self) -> &P;
+    fn get_mut(&mut self) -> &mut P;
+}
+
+fn get<T, P, const ID: usize>(r: &T) -> &P
+where
+    T: Has<P, ID>,
+{
+    <T as Has<P, ID>>::get(r)
+}
+
+fn update<T, P, const ID: usize, F>(r: &T, f: F) -> T
+where
+    T: Has<P, ID>,
+    F: FnOnce(&mut P),
+{
+    let mut s = r.clone();
+    f(<T as Has<P, ID>>::get_mut(&mut s));
+    s
+}
+
+fn always_true<T>(_r: &T) -> bool {
+    true
+}
+
+fn return_copied<T: Clone>(r: &T) -> T {
+    r.clone()
+}
+
+fn label(op: &str, name: &str) -> String {
+    format!("{} {}", op, name)
+}
+
+// エラーメッセージ用に持ち主を "held by process 1" や "not held" と表す
+fn held_by(owner: Option<usize>) -> String {
+    match owner {
+        Some(p) => format!("held by process {}", p),
+        None => String::from("not held"),
+    }
+}
+
+/// 排他ロック
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Mutex {
+    pub owner: Option<usize>,
+}
+
+/// 同じプロセスなら何度でもロックできる排他ロック
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct RecursiveMutex {
+    pub owner: Option<usize>,
+    pub depth: u32,
+}
+
+/// 条件変数。`wait` したプロセスは `signal` か `broadcast` で外されるまで待つ
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Condvar {
+    pub waiters: BTreeSet<usize>,
+}
+
+/// 計数セマフォ。`count` は `max` を超えない
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Semaphore {
+    pub count: u32,
+    pub max: u32,
+}
+
+impl Semaphore {
+    pub fn new(count: u32, max: u32) -> Semaphore {
+        Semaphore { count, max }
+    }
+}
+
+/// 読み書きロック。読み手は何人でも入れるが、書き手は1人だけ
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct RwLock {
+    pub readers: BTreeSet<usize>,
+    pub writer: Option<usize>,
+}
+
+/// `parties` 個のプロセスがそろうまで待たせるバリア
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Barrier {
+    pub parties: usize,
+    /// 今の回にすでに到着したプロセス。全員そろうと空に戻る
+    pub arrived: BTreeSet<usize>,
+}
+
+impl Barrier {
+    pub fn new(parties: usize) -> Barrier {
+        Barrier {
+            parties,
+            arrived: BTreeSet::new(),
+        }
+    }
+}
+
+/// 一度だけ実行する初期化。実行中に来たプロセスは終わるまで待つ
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Once {
+    pub runner: Option<usize>,
+    pub done: bool,
+}
+
+pub mod mutex {
+    use super::*;
+
+    pub fn can_lock<T, const M: usize>(r: &T) -> bool
+    where
+        T: Has<Mutex, M>,
+    {
+        get::<T, Mutex, M>(r).owner.is_none()
+    }
+
+    pub fn lock<T, const M: usize, const P: usize>(r: &T) -> T
+    where
+        T: Has<Mutex, M>,
+    {
+        update::<T, Mutex, M, _>(r, |m| m.owner = Some(P))
+    }
+
+    pub fn unlock<T, const M: usize, const P: usize>(r: &T) -> Result<T, String>
+    where
+        T: Has<Mutex, M>,
+    {
+        match get::<T, Mutex, M>(r).owner {
+            Some(owner) if owner == P => Ok(update::<T, Mutex, M, _>(r, |m| m.owner = None)),
+            owner => Err(format!(
+                "process {} unlocked mutex {} {}",
+                P,
+                M,
+                held_by(owner)
+            )),
+        }
+    }
+
+    pub fn lock_trans<T, const M: usize, const P: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<Mutex, M>,
+    {
+        Trans::new(
+            &label("lock", name),
+            target,
+            can_lock::<T, M>,
+            lock::<T, M, P>,
+        )
+    }
+
+    pub fn unlock_trans<T, const M: usize, const P: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<Mutex, M>,
+    {
+        Trans::check(
+            &label("unlock", name),
+            target,
+            always_true,
+            unlock::<T, M, P>,
+        )
+    }
+}
+
+pub mod recursive_mutex {
+    use super::*;
+
+    pub fn can_lock<T, const M: usize, const P: usize>(r: &T) -> bool
+    where
+        T: Has<RecursiveMutex, M>,
+    {
+        get::<T, RecursiveMutex, M>(r)
+            .owner
+            .is_none_or(|owner| owner == P)
+    }
+
+    pub fn lock<T, const M: usize, const P: usize>(r: &T) -> T
+    where
+        T: Has<RecursiveMutex, M>,
+    {
+        update::<T, RecursiveMutex, M, _>(r, |m| {
+            m.owner = Some(P);
+            m.depth += 1;
+        })
+    }
+
+    pub fn unlock<T, const M: usize, const P: usize>(r: &T) -> Result<T, String>
+    where
+        T: Has<RecursiveMutex, M>,
+    {
+        match get::<T, RecursiveMutex, M>(r).owner {
+            Some(owner) if owner == P => Ok(update::<T, RecursiveMutex, M, _>(r, |m| {
+                m.depth -= 1;
+                if m.depth == 0 {
+                    m.owner = None;
+                }
+            })),
+            owner => Err(format!(
+                "process {} unlocked recursive mutex {} {}",
+                P,
+                M,
+                held_by(owner)
+            )),
+        }
+    }
+
+    pub fn lock_trans<T, const M: usize, const P: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<RecursiveMutex, M>,
+    {
+        Trans::new(
+            &label("lock", name),
+            target,
+            can_lock::<T, M, P>,
+            lock::<T, M, P>,
+        )
+    }
+
+    pub fn unlock_trans<T, const M: usize, const P: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<RecursiveMutex, M>,
+    {
+        Trans::check(
+            &label("unlock", name),
+            target,
+            always_true,
+            unlock::<T, M, P>,
+        )
+    }
+}
+
+/// 条件変数の操作。`wait` は排他ロック `M` を手放して待ち、`wakeup` で `M` を取り直して戻る
+pub mod condvar {
+    use super::*;
+
+    pub fn wait<T, const C: usize, const M: usize, const P: usize>(r: &T) -> Result<T, String>
+    where
+        T: Has<Condvar, C> + Has<Mutex, M>,
+    {
+        match get::<T, Mutex, M>(r).owner {
+            Some(owner) if owner == P => {
+                let s = update::<T, Mutex, M, _>(r, |m| m.owner = None);
+                Ok(update::<T, Condvar, C, _>(&s, |c| {
+                    c.waiters.insert(P);
+                }))
+            }
+            owner => Err(format!(
+                "process {} waited on condvar {} without holding mutex {} ({})",
+                P,
+                C,
+                M,
+                held_by(owner)
+            )),
+        }
+    }
+
+    pub fn can_wakeup<T, const C: usize, const M: usize, const P: usize>(r: &T) -> bool
+    where
+        T: Has<Condvar, C> + Has<Mutex, M>,
+    {
+        !get::<T, Condvar, C>(r).waiters.contains(&P) && get::<T, Mutex, M>(r).owner.is_none()
+    }
+
+    /// 待っているプロセスのどれか1つを起こす。誰も待っていなければ何もしない
+    pub fn signal<T, const C: usize>(r: &T) -> Vec<T>
+    where
+        T: Has<Condvar, C>,
+    {
+        let waiters = &get::<T, Condvar, C>(r).waiters;
+        if waiters.is_empty() {
+            return vec![r.clone()];
+        }
+        waiters
+            .iter()
+            .map(|p| {
+                update::<T, Condvar, C, _>(r, |c| {
+                    c.waiters.remove(p);
+                })
+            })
+            .collect()
+    }
+
+    pub fn broadcast<T, const C: usize>(r: &T) -> T
+    where
+        T: Has<Condvar, C>,
+    {
+        update::<T, Condvar, C, _>(r, |c| c.waiters.clear())
+    }
+
+    pub fn wait_trans<T, const C: usize, const M: usize, const P: usize>(
+        name: &str,
+        target: &str,
+    ) -> Trans<T>
+    where
+        T: Has<Condvar, C> + Has<Mutex, M>,
+    {
+        Trans::check(
+            &label("wait", name),
+            target,
+            always_true,
+            wait::<T, C, M, P>,
+        )
+    }
+
+    pub fn wakeup_trans<T, const C: usize, const M: usize, const P: usize>(
+        name: &str,
+        target: &str,
+    ) -> Trans<T>
+    where
+        T: Has<Condvar, C> + Has<Mutex, M>,
+    {
+        Trans::new(
+            &label("wakeup", name),
+            target,
+            can_wakeup::<T, C, M, P>,
+            mutex::lock::<T, M, P>,
+        )
+    }
+
+    pub fn signal_trans<T, const C: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<Condvar, C>,
+    {
+        Trans::choice(&label("signal", name), target, always_true, signal::<T, C>)
+    }
+
+    pub fn broadcast_trans<T, const C: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<Condvar, C>,
+    {
+        Trans::new(
+            &label("broadcast", name),
+            target,
+            always_true,
+            broadcast::<T, C>,
+        )
+    }
+}
+
+pub mod semaphore {
+    use super::*;
+
+    pub fn can_acquire<T, const S: usize>(r: &T) -> bool
+    where
+        T: Has<Semaphore, S>,
+    {
+        get::<T, Semaphore, S>(r).count > 0
+    }
+
+    pub fn acquire<T, const S: usize>(r: &T) -> T
+    where
+        T: Has<Semaphore, S>,
+    {
+        update::<T, Semaphore, S, _>(r, |s| s.count -= 1)
+    }
+
+    pub fn release<T, const S: usize>(r: &T) -> Result<T, String>
+    where
+        T: Has<Semaphore, S>,
+    {
+        let sem = get::<T, Semaphore, S>(r);
+        if sem.count < sem.max {
+            Ok(update::<T, Semaphore, S, _>(r, |s| s.count += 1))
+        } else {
+            Err(format!(
+                "semaphore {} released above its maximum {}",
+                S, sem.max
+            ))
+        }
+    }
+
+    pub fn acquire_trans<T, const S: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<Semaphore, S>,
+    {
+        Trans::new(
+            &label("acquire", name),
+            target,
+            can_acquire::<T, S>,
+            acquire::<T, S>,
+        )
+    }
+
+    pub fn release_trans<T, const S: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<Semaphore, S>,
+    {
+        Trans::check(
+            &label("release", name),
+            target,
+            always_true,
+            release::<T, S>,
+        )
+    }
+}
+
+pub mod rwlock {
+    use super::*;
+
+    pub fn can_read_lock<T, const L: usize>(r: &T) -> bool
+    where
+        T: Has<RwLock, L>,
+    {
+        get::<T, RwLock, L>(r).writer.is_none()
+    }
+
+    pub fn read_lock<T, const L: usize, const P: usize>(r: &T) -> T
+    where
+        T: Has<RwLock, L>,
+    {
+        update::<T, RwLock, L, _>(r, |l| {
+            l.readers.insert(P);
+        })
+    }
+
+    pub fn read_unlock<T, const L: usize, const P: usize>(r: &T) -> Result<T, String>
+    where
+        T: Has<RwLock, L>,
+    {
+        if get::<T, RwLock, L>(r).readers.contains(&P) {
+            Ok(update::<T, RwLock, L, _>(r, |l| {
+                l.readers.remove(&P);
+            }))
+        } else {
+            Err(format!(
+                "process {} read-unlocked rwlock {} without reading",
+                P, L
+            ))
+        }
+    }
+
+    pub fn can_write_lock<T, const L: usize>(r: &T) -> bool
+    where
+        T: Has<RwLock, L>,
+    {
+        let l = get::<T, RwLock, L>(r);
+        l.writer.is_none() && l.readers.is_empty()
+    }
+
+    pub fn write_lock<T, const L: usize, const P: usize>(r: &T) -> T
+    where
+        T: Has<RwLock, L>,
+    {
+        update::<T, RwLock, L, _>(r, |l| l.writer = Some(P))
+    }
+
+    pub fn write_unlock<T, const L: usize, const P: usize>(r: &T) -> Result<T, String>
+    where
+        T: Has<RwLock, L>,
+    {
+        match get::<T, RwLock, L>(r).writer {
+            Some(writer) if writer == P => Ok(update::<T, RwLock, L, _>(r, |l| l.writer = None)),
+            writer => Err(format!(
+                "process {} write-unlocked rwlock {} {}",
+                P,
+                L,
+                held_by(writer)
+            )),
+        }
+    }
+
+    pub fn read_lock_trans<T, const L: usize, const P: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<RwLock, L>,
+    {
+        Trans::new(
+            &label("read_lock", name),
+            target,
+            can_read_lock::<T, L>,
+            read_lock::<T, L, P>,
+        )
+    }
+
+    pub fn read_unlock_trans<T, const L: usize, const P: usize>(
+        name: &str,
+        target: &str,
+    ) -> Trans<T>
+    where
+        T: Has<RwLock, L>,
+    {
+        Trans::check(
+            &label("read_unlock", name),
+            target,
+            always_true,
+            read_unlock::<T, L, P>,
+        )
+    }
+
+    pub fn write_lock_trans<T, const L: usize, const P: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<RwLock, L>,
+    {
+        Trans::new(
+            &label("write_lock", name),
+            target,
+            can_write_lock::<T, L>,
+            write_lock::<T, L, P>,
+        )
+    }
+
+    pub fn write_unlock_trans<T, const L: usize, const P: usize>(
+        name: &str,
+        target: &str,
+    ) -> Trans<T>
+    where
+        T: Has<RwLock, L>,
+    {
+        Trans::check(
+            &label("write_unlock", name),
+            target,
+            always_true,
+            write_unlock::<T, L, P>,
+        )
+    }
+}
+
+/// バリアの操作。`arrive` で到着して、全員そろったら `pass` で先に進む
+pub mod barrier {
+    use super::*;
+
+    pub fn arrive<T, const B: usize, const P: usize>(r: &T) -> Result<T, String>
+    where
+        T: Has<Barrier, B>,
+    {
+        if get::<T, Barrier, B>(r).arrived.contains(&P) {
+            return Err(format!("process {} arrived at barrier {} twice", P, B));
+        }
+        Ok(update::<T, Barrier, B, _>(r, |b| {
+            b.arrived.insert(P);
+            if b.arrived.len() == b.parties {
+                b.arrived.clear();
+            }
+        }))
+    }
+
+    pub fn can_pass<T, const B: usize, const P: usize>(r: &T) -> bool
+    where
+        T: Has<Barrier, B>,
+    {
+        !get::<T, Barrier, B>(r).arrived.contains(&P)
+    }
+
+    pub fn arrive_trans<T, const B: usize, const P: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<Barrier, B>,
+    {
+        Trans::check(
+            &label("arrive", name),
+            target,
+            always_true,
+            arrive::<T, B, P>,
+        )
+    }
+
+    pub fn pass_trans<T, const B: usize, const P: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<Barrier, B>,
+    {
+        Trans::new(
+            &label("pass", name),
+            target,
+            can_pass::<T, B, P>,
+            return_copied,
+        )
+    }
+}
+
+/// 一度だけの初期化の操作。最初に来たプロセスが `start` から `finish` まで初期化を行い、
+/// 終わった後に来たプロセスは `skip` で通り過ぎる
+pub mod once {
+    use super::*;
+
+    pub fn can_start<T, const O: usize>(r: &T) -> bool
+    where
+        T: Has<Once, O>,
+    {
+        let o = get::<T, Once, O>(r);
+        o.runner.is_none() && !o.done
+    }
+
+    pub fn start<T, const O: usize, const P: usize>(r: &T) -> T
+    where
+        T: Has<Once, O>,
+    {
+        update::<T, Once, O, _>(r, |o| o.runner = Some(P))
+    }
+
+    pub fn finish<T, const O: usize, const P: usize>(r: &T) -> Result<T, String>
+    where
+        T: Has<Once, O>,
+    {
+        match get::<T, Once, O>(r).runner {
+            Some(runner) if runner == P => Ok(update::<T, Once, O, _>(r, |o| {
+                o.runner = None;
+                o.done = true;
+            })),
+            Some(runner) => Err(format!(
+                "process {} finished once {} run by process {}",
+                P, O, runner
+            )),
+            None => Err(format!(
+                "process {} finished once {} that is not running",
+                P, O
+            )),
+        }
+    }
+
+    pub fn can_skip<T, const O: usize>(r: &T) -> bool
+    where
+        T: Has<Once, O>,
+    {
+        get::<T, Once, O>(r).done
+    }
+
+    pub fn start_trans<T, const O: usize, const P: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<Once, O>,
+    {
+        Trans::new(
+            &label("start", name),
+            target,
+            can_start::<T, O>,
+            start::<T, O, P>,
+        )
+    }
+
+    pub fn finish_trans<T, const O: usize, const P: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<Once, O>,
+    {
+        Trans::check(
+            &label("finish", name),
+            target,
+            always_true,
+            finish::<T, O, P>,
+        )
+    }
+
+    pub fn skip_trans<T, const O: usize>(name: &str, target: &str) -> Trans<T>
+    where
+        T: Has<Once, O>,
+    {
+        Trans::new(
+            &label("skip", name),
+            target,
+            can_skip::<T, O>,
+            return_copied,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddsv::data::{concurrent_composition, lts_states, Lts, Process};
+
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    struct Vars {
+        m: Mutex,
+        rm: RecursiveMutex,
+        cv: Condvar,
+        sem: Semaphore,
+        rw: RwLock,
+        b: Barrier,
+        once: Once,
+    }
+
+    impl Vars {
+        fn new() -> Vars {
+            Vars {
+                m: Mutex::default(),
+                rm: RecursiveMutex::default(),
+                cv: Condvar::default(),
+                sem: Semaphore::new(2, 2),
+                rw: RwLock::default(),
+                b: Barrier::new(2),
+                once: Once::default(),
+            }
+        }
+    }
+
+    macro_rules! has {
+        ($p:ty, $field:ident) => {
+            impl Has<$p, 0> for Vars {
+                fn get(&self) -> &$p {
+                    &self.$field
+                }
+                fn get_mut(&mut self) -> &mut $p {
+                    &mut self.$field
+                }
+            }
+        };
+    }
+    has!(Mutex, m);
+    has!(RecursiveMutex, rm);
+    has!(Condvar, cv);
+    has!(Semaphore, sem);
+    has!(RwLock, rw);
+    has!(Barrier, b);
+    has!(Once, once);
+
+    fn always_true(_r: &Vars) -> bool {
+        true
+    }
+    fn return_copied(r: &Vars) -> Vars {
+        r.clone()
+    }
+
+    // 到達したすべての状態の場所の組
+    fn reachable(lts: &Lts<Vars>) -> Vec<Vec<String>> {
+        lts_states(lts)
+            .map(|((_, locs), _, _)| lts.location_names(locs))
+            .collect()
+    }
+
+    fn locations_of(lts: &Lts<Vars>, id: i32) -> Vec<String> {
+        lts.trace_to(id).unwrap().pop().unwrap().1 .1
+    }
+
+    fn both_in(lts: &Lts<Vars>, p: &str, q: &str) -> bool {
+        reachable(lts)
+            .iter()
+            .any(|locs| locs.iter().any(|l| l == p) && locs.iter().any(|l| l == q))
+    }
+
+    #[test]
+    fn mutex_test() {
+        let ps = vec![
+            Process::new(vec![
+                ("P0", vec![mutex::lock_trans::<_, 0, 0>("m", "P1")]),
+                ("P1", vec![mutex::unlock_trans::<_, 0, 0>("m", "P0")]),
+            ]),
+            Process::new(vec![
+                ("Q0", vec![mutex::lock_trans::<_, 0, 1>("m", "Q1")]),
+                ("Q1", vec![mutex::unlock_trans::<_, 0, 1>("m", "Q0")]),
+            ]),
+        ];
+        assert_eq!(ps[0].0[0].1[0].label, "lock m");
+        let lts = concurrent_composition(&Vars::new(), &ps).unwrap();
+        assert_eq!(lts.states.len(), 3);
+        assert!(!both_in(&lts, "P1", "Q1"));
+        assert!(lts.deadlocks.is_empty() && lts.violations.is_empty());
+
+        // 持っていないロックを外す
+        let ps = vec![Process::new(vec![
+            ("P0", vec![mutex::unlock_trans::<_, 0, 0>("m", "P1")]),
+            ("P1", vec![]),
+        ])];
+        let lts = concurrent_composition(&Vars::new(), &ps).unwrap();
+        assert_eq!(
+            lts.violations[0].message,
+            "process 0 unlocked mutex 0 not held"
+        );
+
+        // 他のプロセスが持っているロックを外す
+        let ps = vec![
+            Process::new(vec![
+                ("P0", vec![mutex::lock_trans::<_, 0, 0>("m", "P1")]),
+                ("P1", vec![]),
+            ]),
+            Process::new(vec![
+                ("Q0", vec![mutex::unlock_trans::<_, 0, 1>("m", "Q1")]),
+                ("Q1", vec![]),
+            ]),
+        ];
+        let lts = concurrent_composition(&Vars::new(), &ps).unwrap();
+        let messages = lts
+            .violations
+            .iter()
+            .map(|v| v.message.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            messages,
+            [
+                "process 1 unlocked mutex 0 not held",
+                "process 1 unlocked mutex 0 held by process 0"
+            ]
+        );
+    }
+
+    // ロックを2重にかけてから外す
+    fn recursive_user<const P: usize>(p: &str) -> Process<Vars> {
+        let l = (0..3).map(|j| format!("{}{}", p, j)).collect::<Vec<_>>();
+        Process::new(vec![
+            (
+                &l[0],
+                vec![recursive_mutex::lock_trans::<_, 0, P>("rm", &l[1])],
+            ),
+            (
+                &l[1],
+                vec![
+                    recursive_mutex::lock_trans::<_, 0, P>("rm", &l[2]),
+                    recursive_mutex::unlock_trans::<_, 0, P>("rm", &l[0]),
+                ],
+            ),
+            (
+                &l[2],
+                vec![recursive_mutex::unlock_trans::<_, 0, P>("rm", &l[1])],
+            ),
+        ])
+    }
+
+    #[test]
+    fn recursive_mutex_test() {
+        let ps = vec![recursive_user::<0>("P"), recursive_user::<1>("Q")];
+        let lts = concurrent_composition(&Vars::new(), &ps).unwrap();
+        assert_eq!(lts.states.len(), 5);
+        assert!(!both_in(&lts, "P1", "Q1"));
+        assert!(lts_states(&lts).all(|((r, _), _, _)| r.rm.depth <= 2));
+        assert!(lts.deadlocks.is_empty() && lts.violations.is_empty());
+    }
+
+    #[test]
+    fn condvar_test() {
+        let mut r = Vars::new();
+        r.cv.waiters = [0, 1].iter().copied().collect();
+        let woken = condvar::signal::<_, 0>(&r)
+            .iter()
+            .map(|s| s.cv.waiters.iter().copied().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(woken, [[1], [0]]);
+        assert!(condvar::broadcast::<_, 0>(&r).cv.waiters.is_empty());
+        assert_eq!(condvar::signal::<_, 0>(&Vars::new()), [Vars::new()]);
+
+        // 待つ前に signal されると起こされずに止まる
+        let ps = vec![
+            Process::new(vec![
+                ("P0", vec![mutex::lock_trans::<_, 0, 0>("m", "P1")]),
+                ("P1", vec![condvar::wait_trans::<_, 0, 0, 0>("cv", "P2")]),
+                ("P2", vec![condvar::wakeup_trans::<_, 0, 0, 0>("cv", "P3")]),
+                ("P3", vec![mutex::unlock_trans::<_, 0, 0>("m", "P4")]),
+                ("P4", vec![]),
+            ]),
+            Process::new(vec![
+                ("Q0", vec![mutex::lock_trans::<_, 0, 1>("m", "Q1")]),
+                ("Q1", vec![condvar::signal_trans::<_, 0>("cv", "Q2")]),
+                ("Q2", vec![mutex::unlock_trans::<_, 0, 1>("m", "Q3")]),
+                ("Q3", vec![]),
+            ]),
+        ];
+        let lts = concurrent_composition(&Vars::new(), &ps).unwrap();
+        let stuck = lts
+            .deadlocks
+            .iter()
+            .map(|id| locations_of(&lts, *id))
+            .collect::<Vec<_>>();
+        assert_eq!(stuck.len(), 2);
+        assert!(stuck.contains(&vec![String::from("P4"), String::from("Q3")]));
+        assert!(stuck.contains(&vec![String::from("P2"), String::from("Q3")]));
+        assert!(lts.violations.is_empty());
+    }
+
+    #[test]
+    fn semaphore_test() {
+        let ps = (0..3)
+            .map(|i| {
+                let (l0, l1) = (format!("P{}_0", i), format!("P{}_1", i));
+                Process::new(vec![
+                    (&l0, vec![semaphore::acquire_trans::<_, 0>("sem", &l1)]),
+                    (&l1, vec![semaphore::release_trans::<_, 0>("sem", &l0)]),
+                ])
+            })
+            .collect::<Vec<_>>();
+        let lts = concurrent_composition(&Vars::new(), &ps).unwrap();
+        assert!(reachable(&lts).iter().all(|locs| locs
+            .iter()
+            .filter(|l| l.ends_with("_1"))
+            .count()
+            <= 2));
+        assert!(lts.deadlocks.is_empty() && lts.violations.is_empty());
+
+        let mut r = Vars::new();
+        r.sem.count = 2;
+        assert_eq!(
+            semaphore::release::<_, 0>(&r).unwrap_err(),
+            "semaphore 0 released above its maximum 2"
+        );
+    }
+
+    fn reader<const P: usize>(p: &str) -> Process<Vars> {
+        let (l0, l1) = (format!("{}0", p), format!("{}1", p));
+        Process::new(vec![
+            (&l0, vec![rwlock::read_lock_trans::<_, 0, P>("rw", &l1)]),
+            (&l1, vec![rwlock::read_unlock_trans::<_, 0, P>("rw", &l0)]),
+        ])
+    }
+
+    #[test]
+    fn rwlock_test() {
+        let writer = Process::new(vec![
+            ("W0", vec![rwlock::write_lock_trans::<_, 0, 2>("rw", "W1")]),
+            (
+                "W1",
+                vec![rwlock::write_unlock_trans::<_, 0, 2>("rw", "W0")],
+            ),
+        ]);
+        let ps = vec![reader::<0>("R"), reader::<1>("S"), writer];
+        let lts = concurrent_composition(&Vars::new(), &ps).unwrap();
+        assert!(both_in(&lts, "R1", "S1"));
+        assert!(!both_in(&lts, "R1", "W1") && !both_in(&lts, "S1", "W1"));
+        assert!(lts.deadlocks.is_empty() && lts.violations.is_empty());
+    }
+
+    #[test]
+    fn barrier_test() {
+        let ps = vec![
+            Process::new(vec![
+                ("P0", vec![barrier::arrive_trans::<_, 0, 0>("b", "P1")]),
+                ("P1", vec![barrier::pass_trans::<_, 0, 0>("b", "P2")]),
+                ("P2", vec![]),
+            ]),
+            Process::new(vec![
+                ("Q0", vec![barrier::arrive_trans::<_, 0, 1>("b", "Q1")]),
+                ("Q1", vec![barrier::pass_trans::<_, 0, 1>("b", "Q2")]),
+                ("Q2", vec![]),
+            ]),
+        ];
+        let lts = concurrent_composition(&Vars::new(), &ps).unwrap();
+        assert!(!both_in(&lts, "P2", "Q0") && !both_in(&lts, "P0", "Q2"));
+        assert_eq!(lts.deadlocks.len(), 1);
+        assert_eq!(locations_of(&lts, lts.deadlocks[0]), ["P2", "Q2"]);
+    }
+
+    // 初期化してから使う
+    fn once_user<const P: usize>(p: &str) -> Process<Vars> {
+        let l = (0..3).map(|j| format!("{}{}", p, j)).collect::<Vec<_>>();
+        Process::new(vec![
+            (
+                &l[0],
+                vec![
+                    once::start_trans::<_, 0, P>("init", &l[1]),
+                    once::skip_trans::<_, 0>("init", &l[2]),
+                ],
+            ),
+            (&l[1], vec![once::finish_trans::<_, 0, P>("init", &l[2])]),
+            (
+                &l[2],
+                vec![Trans::new("use", &l[2], always_true, return_copied)],
+            ),
+        ])
+    }
+
+    #[test]
+    fn once_test() {
+        let ps = vec![once_user::<0>("P"), once_user::<1>("Q")];
+        let lts = concurrent_composition(&Vars::new(), &ps).unwrap();
+        assert!(!both_in(&lts, "P1", "Q1"));
+        assert!(both_in(&lts, "P2", "Q2"));
+        assert!(lts_states(&lts).all(|((r, locs), _, _)| r.once.done
+            || !lts.location_names(locs).iter().any(|l| l.ends_with('2'))));
+        assert!(lts.deadlocks.is_empty() && lts.violations.is_empty());
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use super::data::Process;
+use super::{Label, Location, State};
+
+/// モデルの定義の誤り。`process` はプロセスの番号
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModelError {
+    /// 場所が1つもないプロセス
+    EmptyProcess { process: usize },
+    /// 同じ名前の場所が複数ある
+    DuplicateLocation { process: usize, location: Location },
+    /// 遷移先の場所が宣言されていない
+    UndeclaredTarget {
+        process: usize,
+        location: Location,
+        label: Label,
+        target: Location,
+    },
+    /// 最初の場所からプロセス内の遷移で到達できない場所
+    UnreachableLocation { process: usize, location: Location },
+    /// 状態の場所の数とプロセスの数が一致しない
+    LocationCountMismatch { processes: usize, locations: usize },
+    /// 状態の場所がプロセスで宣言されていない
+    UnknownLocation { process: usize, location: Location },
+    /// `Program` の不可分なブロックの中に繰り返しがある
+    LoopInAtomic { label: Label },
+    /// 不可分な列の場所がプロセスで宣言されていない
+    UndeclaredAtomicLocation { location: Location },
+    /// LTSのプロセスの場所が、渡したプロセスの場所と一致しない
+    LtsMismatch { process: usize },
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match self {
+            ModelError::EmptyProcess { process } => {
+                write!(f, "process {} has no locations", process)
+            }
+            ModelError::DuplicateLocation { process, location } => write!(
+                f,
+                "process {} declares location {} more than once",
+                process, location
+            ),
+            ModelError::UndeclaredTarget {
+                process,
+                location,
+                label,
+                target,
+            } => write!(
+                f,
+                "process {}: transition \"{}\" from {} targets undeclared location {}",
+                process, label, location, target
+            ),
+            ModelError::UnreachableLocation { process, location } => write!(
+                f,
+                "process {}: location {} is unreachable",
+                process, location
+            ),
+            ModelError::LocationCountMismatch {
+                processes,
+                locations,
+            } => write!(
+                f,
+                "{} processes but the state has {} locations",
+                processes, locations
+            ),
+            ModelError::UnknownLocation { process, location } => {
+                write!(f, "process {} has no location {}", process, location)
+            }
+            ModelError::UndeclaredAtomicLocation { location } => {
+                write!(f, "atomic sequence has undeclared location {}", location)
+            }
+            ModelError::LtsMismatch { process } => write!(
+                f,
+                "process {} does not match the locations of the LTS",
+                process
+            ),
+            ModelError::LoopInAtomic { label } => {
+                write!(
+                    f,
+                    "while \"{}\" cannot be used inside an atomic block",
+                    label
+                )
+            }
+        }
+    }
+}
+
+fn validate_process<T>(i: usize, p: &Process<T>, errors: &mut Vec<ModelError>) {
+    if p.0.is_empty() {
+        errors.push(ModelError::EmptyProcess { process: i });
+        return;
+    }
+    let mut declared = HashSet::new();
+    for (location, _) in &p.0 {
+        if !declared.insert(location) {
+            errors.push(ModelError::DuplicateLocation {
+                process: i,
+                location: location.clone(),
+            });
+        }
+    }
+    for (location, trans) in &p.0 {
+        for t in trans {
+            if !declared.contains(&t.location) {
+                errors.push(ModelError::UndeclaredTarget {
+                    process: i,
+                    location: location.clone(),
+                    label: t.label.clone(),
+                    target: t.location.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// プロセスの定義を検査し、見つかった誤りをすべて返す。到達できない場所は誤りとせず、
+/// `lint_unreachable` で別に検査する
+pub fn validate<T>(ps: &[Process<T>]) -> Result<(), Vec<ModelError>> {
+    let mut errors = vec![];
+    for (i, p) in ps.iter().enumerate() {
+        validate_process(i, p, &mut errors);
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+/// 最初の場所からプロセス内の遷移で到達できない場所を警告として返す。
+/// 到達できない場所があっても合成はできる
+pub fn lint_unreachable<T>(ps: &[Process<T>]) -> Vec<ModelError> {
+    let mut warnings = vec![];
+    for (i, p) in ps.iter().enumerate().filter(|(_, p)| !p.0.is_empty()) {
+        let mut reached = HashSet::new();
+        let mut stack = vec![&p.0[0].0];
+        while let Some(location) = stack.pop() {
+            if reached.insert(location) {
+                for (_, trans) in p.0.iter().filter(|(l, _)| l == location) {
+                    stack.extend(trans.iter().map(|t| &t.location));
+                }
+            }
+        }
+        let mut reported = HashSet::new();
+        for (location, _) in &p.0 {
+            if !reached.contains(location) && reported.insert(location) {
+                warnings.push(ModelError::UnreachableLocation {
+                    process: i,
+                    location: location.clone(),
+                });
+            }
+        }
+    }
+    warnings
+}
+
+/// 状態の場所がプロセスの定義と対応しているかを検査する
+pub fn validate_state<T>(ps: &[Process<T>], state: &State<T>) -> Result<(), Vec<ModelError>> {
+    let (_, locs) = state;
+    if ps.len() != locs.len() {
+        return Err(vec![ModelError::LocationCountMismatch {
+            processes: ps.len(),
+            locations: locs.len(),
+        }]);
+    }
+    let errors = ps
+        .iter()
+        .zip(locs.iter())
+        .enumerate()
+        .filter(|(_, (p, l))| p.0.iter().all(|(location, _)| location != *l))
+        .map(|(i, (_, l))| ModelError::UnknownLocation {
+            process: i,
+            location: l.clone(),
+        })
+        .collect::<Vec<_>>();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ddsv::data::Trans;
+
+    fn always_true(_r: &i32) -> bool {
+        true
+    }
+    fn keep(r: &i32) -> i32 {
+        *r
+    }
+
+    #[test]
+    fn validate_ok_test() {
+        let ps = [Process::new(vec![
+            ("P0", vec![Trans::new("a", "P1", always_true, keep)]),
+            ("P1", vec![]),
+        ])];
+        assert_eq!(validate(&ps), Ok(()));
+        assert_eq!(validate_state(&ps, &(0, vec![String::from("P1")])), Ok(()));
+    }
+
+    #[test]
+    fn validate_errors_test() {
+        let p = Process::new(vec![
+            ("P0", vec![Trans::new("a", "P9", always_true, keep)]),
+            ("P1", vec![]),
+            ("P1", vec![]),
+        ]);
+        let errors = validate(&[p, Process(vec![])]).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                ModelError::DuplicateLocation {
+                    process: 0,
+                    location: String::from("P1"),
+                },
+                ModelError::UndeclaredTarget {
+                    process: 0,
+                    location: String::from("P0"),
+                    label: String::from("a"),
+                    target: String::from("P9"),
+                },
+                ModelError::EmptyProcess { process: 1 },
+            ]
+        );
+        assert_eq!(
+            errors[1].to_string(),
+            "process 0: transition \"a\" from P0 targets undeclared location P9"
+        );
+    }
+
+    #[test]
+    fn lint_unreachable_test() {
+        let p = Process::new(vec![
+            ("P0", vec![Trans::new("a", "P1", always_true, keep)]),
+            ("P1", vec![]),
+            ("P2", vec![Trans::new("b", "P1", always_true, keep)]),
+        ]);
+        let ps = [p, Process(vec![])];
+        assert_eq!(
+            lint_unreachable(&ps),
+            [ModelError::UnreachableLocation {
+                process: 0,
+                location: String::from("P2"),
+            }]
+        );
+        assert_eq!(validate(&ps[..1]), Ok(()));
+    }
+
+    #[test]
+    fn validate_state_test() {
+        let ps = [Process::new(vec![("P0", vec![])])];
+        assert_eq!(
+            validate_state(&ps, &(0, vec![])),
+            Err(vec![ModelError::LocationCountMismatch {
+                processes: 1,
+                locations: 0,
+            }])
+        );
+        assert_eq!(
+            validate_state(&ps, &(0, vec![String::from("Q0")])),
+            Err(vec![ModelError::UnknownLocation {
+                process: 0,
+                location: String::from("Q0"),
+            }])
+        );
+    }
+}
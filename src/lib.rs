@@ -1,3 +1,17 @@
 pub mod ddsv;
 
 pub use crate::ddsv::data;
+pub use crate::ddsv::error;
+pub use crate::ddsv::format;
+pub use crate::ddsv::lock_order;
+pub use crate::ddsv::model;
+pub use crate::ddsv::program;
+pub use crate::ddsv::race;
+pub use crate::ddsv::random;
+pub use crate::ddsv::refinement;
+pub use crate::ddsv::replay;
+pub use crate::ddsv::serialize;
+pub use crate::ddsv::simulator;
+pub use crate::ddsv::sweep;
+pub use crate::ddsv::sync;
+pub use crate::ddsv::validate;